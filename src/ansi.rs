@@ -0,0 +1,439 @@
+//! Turns what tools write for a terminal into plain lines and the styles that
+//! go with them. The line being written is kept as a row of cells with a
+//! cursor, so `\r`, backspace and erasing overwrite it the way they would in
+//! a terminal and a progress bar ends up as one line. Colours and text
+//! attributes (SGR) are kept; titles, moving between lines and the like have
+//! nowhere to go in a log, so they are dropped rather than shown as garbage.
+
+use std::mem;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// An unterminated sequence longer than this is given up on, so a stray
+/// `ESC]` can't hold back the rest of the output.
+const MAX_SEQUENCE: usize = 4096;
+
+/// Moving the cursor right stops here, past the end of the line, so
+/// `ESC[65535C` can't pad a line with thousands of spaces.
+const MAX_COLUMN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 256 palette colours, the first 16 being the named ones.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub inverse: bool,
+}
+
+/// Where in a line's text a style takes effect. Text before the first one
+/// has the default style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StyleChange {
+    pub at: usize,
+    pub style: Style,
+}
+
+/// Follows the output of one stream. A style carries on from one line to the
+/// next until it is reset, as it would in a terminal.
+#[derive(Debug, Default)]
+pub struct Parser {
+    style: Style,
+    // the line being written, a character to each cell
+    cells: Vec<(char, Style)>,
+    cursor: usize,
+    // the start of an escape sequence cut off by the end of the last write
+    unfinished: String,
+}
+
+/// What an escape sequence does.
+enum Sequence {
+    /// `ESC[`, its numeric parameters, and the character that ends it.
+    Control(Vec<Option<u16>>, char),
+    Ignored,
+}
+
+impl Parser {
+    /// Takes text the process wrote, which shouldn't include newlines. It
+    /// may stop part way through an escape sequence.
+    pub fn write(&mut self, text: &str) {
+        let joined;
+        let text = match self.unfinished.is_empty() {
+            true => text,
+            false => {
+                joined = mem::take(&mut self.unfinished) + text;
+                &joined
+            }
+        };
+
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if c == ESC {
+                match escape_sequence(rest) {
+                    Some((sequence, length)) => {
+                        self.apply(sequence);
+                        rest = &rest[length..];
+                        continue;
+                    }
+                    None if rest.len() < MAX_SEQUENCE => {
+                        self.unfinished = rest.to_string();
+                        break;
+                    }
+                    // too long to be a sequence, so the ESC was a stray one
+                    // and what follows it is text
+                    None => {
+                        rest = &rest[ESC.len_utf8()..];
+                        continue;
+                    }
+                }
+            }
+            match c {
+                '\r' => self.cursor = 0,
+                '\x08' => self.cursor = self.cursor.saturating_sub(1),
+                '\t' => self.put(c),
+                c if c.is_control() => {}
+                c => self.put(c),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    /// The line written so far, as its text and where its style changes.
+    pub fn line(&self) -> (String, Vec<StyleChange>) {
+        let mut text = String::with_capacity(self.cells.len());
+        let mut changes = vec![];
+        let mut current = Style::default();
+        for &(c, style) in &self.cells {
+            if style != current {
+                changes.push(StyleChange {
+                    at: text.len(),
+                    style,
+                });
+                current = style;
+            }
+            text.push(c);
+        }
+        (text, changes)
+    }
+
+    /// Finishes the line written so far, ready to start the next.
+    pub fn end_line(&mut self) -> (String, Vec<StyleChange>) {
+        // a sequence the line ends in the middle of never was one
+        while !self.unfinished.is_empty() {
+            let unfinished = mem::take(&mut self.unfinished);
+            self.write(&unfinished[ESC.len_utf8()..]);
+        }
+        let line = self.line();
+        self.cells.clear();
+        self.cursor = 0;
+        line
+    }
+
+    fn put(&mut self, c: char) {
+        if self.cursor > self.cells.len() {
+            self.cells.resize(self.cursor, (' ', Style::default()));
+        }
+        match self.cells.get_mut(self.cursor) {
+            Some(cell) => *cell = (c, self.style),
+            None => self.cells.push((c, self.style)),
+        }
+        self.cursor += 1;
+    }
+
+    fn apply(&mut self, sequence: Sequence) {
+        let Sequence::Control(parameters, command) = sequence else {
+            return;
+        };
+        let count = parameters.first().copied().flatten().unwrap_or(1).max(1) as usize;
+        match command {
+            'm' => self.select_graphic_rendition(&parameters),
+            'C' => self.move_to(self.cursor.saturating_add(count)),
+            'D' => self.cursor = self.cursor.saturating_sub(count),
+            'G' => self.move_to(count - 1),
+            // erasing in the line; the cursor stays where it is
+            'K' => match parameters.first().copied().flatten().unwrap_or(0) {
+                0 => self.cells.truncate(self.cursor),
+                1 => {
+                    let end = (self.cursor + 1).min(self.cells.len());
+                    self.cells[..end].fill((' ', Style::default()));
+                }
+                _ => self.cells.clear(),
+            },
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, column: usize) {
+        self.cursor = column.min(self.cells.len().max(MAX_COLUMN));
+    }
+
+    fn select_graphic_rendition(&mut self, parameters: &[Option<u16>]) {
+        let style = &mut self.style;
+        // `ESC[m` is the same as `ESC[0m`
+        let mut parameters = parameters.iter().map(|p| p.unwrap_or(0)).peekable();
+        if parameters.peek().is_none() {
+            *style = Style::default();
+        }
+        while let Some(parameter) = parameters.next() {
+            match parameter {
+                0 => *style = Style::default(),
+                1 => style.bold = true,
+                2 => style.dim = true,
+                3 => style.italic = true,
+                4 => style.underline = true,
+                7 => style.inverse = true,
+                9 => style.strikethrough = true,
+                21 | 22 => (style.bold, style.dim) = (false, false),
+                23 => style.italic = false,
+                24 => style.underline = false,
+                27 => style.inverse = false,
+                29 => style.strikethrough = false,
+                30..=37 => style.foreground = Some(Color::Indexed((parameter - 30) as u8)),
+                38 => style.foreground = extended_color(&mut parameters),
+                39 => style.foreground = None,
+                40..=47 => style.background = Some(Color::Indexed((parameter - 40) as u8)),
+                48 => style.background = extended_color(&mut parameters),
+                49 => style.background = None,
+                90..=97 => style.foreground = Some(Color::Indexed((parameter - 90 + 8) as u8)),
+                100..=107 => style.background = Some(Color::Indexed((parameter - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reads the escape sequence `text` starts with, returning it and its length,
+/// or `None` if `text` ends before it does.
+fn escape_sequence(text: &str) -> Option<(Sequence, usize)> {
+    let mut chars = text.char_indices().skip(1);
+    let (_, introducer) = chars.next()?;
+    match introducer {
+        '[' => {
+            let mut parameters = vec![];
+            let mut current: Option<u16> = None;
+            for (at, c) in chars {
+                match c {
+                    '0'..='9' => {
+                        let digit = c as u16 - '0' as u16;
+                        let value = current.unwrap_or(0).saturating_mul(10);
+                        current = Some(value.saturating_add(digit));
+                    }
+                    // sub-parameters (`38:2:r:g:b`) are read as parameters
+                    ';' | ':' => parameters.push(current.take()),
+                    // private markers and intermediates
+                    '<'..='?' | ' '..='/' => {}
+                    '@'..='~' => {
+                        if current.is_some() || !parameters.is_empty() {
+                            parameters.push(current);
+                        }
+                        return Some((Sequence::Control(parameters, c), at + 1));
+                    }
+                    // not a valid sequence, so drop what was read of it
+                    _ => return Some((Sequence::Ignored, at)),
+                }
+            }
+            None
+        }
+        // an operating system command such as a window title, which ends
+        // with BEL or `ESC\`
+        ']' => {
+            let mut chars = chars.peekable();
+            while let Some((at, c)) = chars.next() {
+                match c {
+                    BEL => return Some((Sequence::Ignored, at + 1)),
+                    ESC => {
+                        return match chars.peek() {
+                            Some((_, '\\')) => Some((Sequence::Ignored, at + 2)),
+                            Some(_) => Some((Sequence::Ignored, at)),
+                            None => None,
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            None
+        }
+        // character set selection and the like, intermediates then a final
+        // character
+        ' '..='/' => chars
+            .find(|(_, c)| !matches!(c, ' '..='/'))
+            .map(|(at, c)| (Sequence::Ignored, at + c.len_utf8())),
+        c => Some((Sequence::Ignored, 1 + c.len_utf8())),
+    }
+}
+
+/// The colour after a 38 or 48: `5;n` for a palette colour or `2;r;g;b`.
+fn extended_color(parameters: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let mut component = || parameters.next().map(|value| value.min(255) as u8);
+    match component()? {
+        5 => component().map(Color::Indexed),
+        2 => Some(Color::Rgb(component()?, component()?, component()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Style {
+        Style {
+            foreground: Some(Color::Indexed(1)),
+            ..Style::default()
+        }
+    }
+
+    fn parse(parser: &mut Parser, raw: &str) -> (String, Vec<StyleChange>) {
+        parser.write(raw);
+        parser.end_line()
+    }
+
+    #[test]
+    fn colours_are_taken_out_of_the_text() {
+        let mut parser = Parser::default();
+        let (text, styles) = parse(&mut parser, "plain \x1b[31mred\x1b[0m plain");
+        assert_eq!(text, "plain red plain");
+        assert_eq!(
+            styles,
+            vec![
+                StyleChange {
+                    at: 6,
+                    style: red()
+                },
+                StyleChange {
+                    at: 9,
+                    style: Style::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn style_carries_on_to_the_next_line() {
+        let mut parser = Parser::default();
+        parse(&mut parser, "\x1b[31mfirst");
+        let (_, styles) = parse(&mut parser, "second");
+        assert_eq!(
+            styles,
+            vec![StyleChange {
+                at: 0,
+                style: red()
+            }]
+        );
+    }
+
+    #[test]
+    fn extended_colours_and_attributes() {
+        let mut parser = Parser::default();
+        let (_, styles) = parse(&mut parser, "\x1b[1;38;5;208;48;2;1;2;3mx");
+        assert_eq!(
+            styles,
+            vec![StyleChange {
+                at: 0,
+                style: Style {
+                    foreground: Some(Color::Indexed(208)),
+                    background: Some(Color::Rgb(1, 2, 3)),
+                    bold: true,
+                    ..Style::default()
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        let mut parser = Parser::default();
+        let (text, styles) = parse(&mut parser, "\x1b]0;title\x07\x1b[2Kdone\x1b(B\x1b[2Cok");
+        assert_eq!(text, "done  ok");
+        assert!(styles.is_empty());
+    }
+
+    #[test]
+    fn carriage_return_overwrites_the_line() {
+        let mut parser = Parser::default();
+        parser.write("downloading 10%");
+        parser.write("\rdownloading 100%");
+        assert_eq!(parser.end_line(), ("downloading 100%".to_string(), vec![]));
+    }
+
+    #[test]
+    fn carriage_return_keeps_what_is_not_overwritten() {
+        let mut parser = Parser::default();
+        parser.write("abcdef\rXY");
+        assert_eq!(parser.line().0, "XYcdef");
+    }
+
+    #[test]
+    fn sequence_split_across_writes() {
+        let mut parser = Parser::default();
+        parser.write("plain \x1b[3");
+        parser.write("1mred\x1b[");
+        parser.write("0m plain");
+        let (text, styles) = parser.end_line();
+        assert_eq!(text, "plain red plain");
+        assert_eq!(
+            styles,
+            vec![
+                StyleChange {
+                    at: 6,
+                    style: red()
+                },
+                StyleChange {
+                    at: 9,
+                    style: Style::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stray_escape_at_the_end_of_a_line_is_text() {
+        let mut parser = Parser::default();
+        parser.write("a \x1b]not a title");
+        parser.write(" after");
+        assert_eq!(parser.end_line().0, "a ]not a title after");
+        // and nothing of it is left over for the next line
+        assert_eq!(parse(&mut parser, "next").0, "next");
+    }
+
+    #[test]
+    fn overlong_sequence_is_given_up_on() {
+        let mut parser = Parser::default();
+        let long = "x".repeat(MAX_SEQUENCE);
+        parser.write(&format!("\x1b]{}", long));
+        parser.write("\x1b[31mred");
+        let (text, styles) = parser.end_line();
+        assert_eq!(text, format!("]{}red", long));
+        assert_eq!(
+            styles,
+            vec![StyleChange {
+                at: long.len() + 1,
+                style: red()
+            }]
+        );
+    }
+
+    #[test]
+    fn cursor_movement_is_clamped() {
+        let mut parser = Parser::default();
+        let (text, _) = parse(&mut parser, "a\x1b[65535Cb");
+        assert_eq!(text.len(), MAX_COLUMN + 1);
+        let (text, _) = parse(&mut parser, "a\x1b[65535Gb");
+        assert_eq!(text.len(), MAX_COLUMN + 1);
+        // within a line longer than that, it still moves
+        let long = "x".repeat(2 * MAX_COLUMN);
+        let (text, _) = parse(&mut parser, &format!("{}\x1b[{}Gb", long, 1500));
+        assert_eq!(text.find('b'), Some(1499));
+    }
+}
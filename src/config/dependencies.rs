@@ -0,0 +1,86 @@
+//! Ordering processes by their `depends_on` lists.
+
+/// Orders processes so that each one comes after everything it depends on,
+/// keeping list order wherever the dependencies allow. `depends_on[i]` holds
+/// the indices process `i` depends on.
+///
+/// On a cycle, returns the indices going round it, starting and ending with
+/// the same process.
+pub fn start_order(depends_on: &[Vec<usize>]) -> Result<Vec<usize>, Vec<usize>> {
+    let mut order = Vec::with_capacity(depends_on.len());
+    let mut placed = vec![false; depends_on.len()];
+
+    while order.len() < depends_on.len() {
+        let next = (0..depends_on.len())
+            .find(|&i| !placed[i] && depends_on[i].iter().all(|&dependency| placed[dependency]));
+        match next {
+            Some(i) => {
+                placed[i] = true;
+                order.push(i);
+            }
+            None => return Err(find_cycle(depends_on, &placed)),
+        }
+    }
+
+    Ok(order)
+}
+
+/// Every process left unplaced is waiting on another unplaced one, so
+/// following those edges has to come back round eventually.
+fn find_cycle(depends_on: &[Vec<usize>], placed: &[bool]) -> Vec<usize> {
+    let first = placed.iter().position(|placed| !placed).unwrap_or_default();
+    let mut path = vec![first];
+    loop {
+        let current = path[path.len() - 1];
+        let Some(next) = depends_on[current]
+            .iter()
+            .copied()
+            .find(|&dependency| !placed[dependency])
+        else {
+            return path;
+        };
+        if let Some(start) = path.iter().position(|&i| i == next) {
+            let mut cycle = path.split_off(start);
+            cycle.push(next);
+            return cycle;
+        }
+        path.push(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependencies_come_first_in_list_order() {
+        // 0 needs 2, 1 needs nothing, 2 needs 1, 3 needs nothing
+        let depends_on = vec![vec![2], vec![], vec![1], vec![]];
+        assert_eq!(start_order(&depends_on), Ok(vec![1, 2, 0, 3]));
+    }
+
+    #[test]
+    fn no_dependencies_keeps_list_order() {
+        assert_eq!(start_order(&[vec![], vec![], vec![]]), Ok(vec![0, 1, 2]));
+        assert_eq!(start_order(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn cycle_is_reported_going_round() {
+        // 0 is fine, 1 -> 2 -> 3 -> 1
+        let depends_on = vec![vec![], vec![2], vec![3], vec![1]];
+        assert_eq!(start_order(&depends_on), Err(vec![1, 2, 3, 1]));
+    }
+
+    #[test]
+    fn cycle_is_found_behind_what_waits_on_it() {
+        // 0 waits on the cycle 1 <-> 2 without being part of it
+        let depends_on = vec![vec![1], vec![2], vec![1]];
+        assert_eq!(start_order(&depends_on), Err(vec![1, 2, 1]));
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        assert_eq!(start_order(&[vec![0]]), Err(vec![0, 0]));
+    }
+}
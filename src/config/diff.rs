@@ -0,0 +1,179 @@
+use super::ProcessDefinition;
+
+/// What changed between two loads of the config, matched up by process name.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigDiff {
+    pub added: Vec<ProcessDefinition>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedProcess>,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangedProcess {
+    pub definition: ProcessDefinition,
+    pub fields: Vec<&'static str>,
+}
+
+impl ChangedProcess {
+    /// Only these can change without the running process noticing.
+    const LIVE_FIELDS: [&str; 7] = [
+        "auto_start",
+        "depends_on",
+        "ready",
+        "restart",
+        "stop",
+        "scrollback",
+        "strip_color",
+    ];
+
+    pub fn needs_restart(&self) -> bool {
+        self.fields
+            .iter()
+            .any(|field| !Self::LIVE_FIELDS.contains(field))
+    }
+}
+
+impl ConfigDiff {
+    pub fn new(old: &[ProcessDefinition], new: &[ProcessDefinition]) -> Self {
+        let mut diff = ConfigDiff::default();
+
+        for definition in new {
+            match old.iter().find(|o| o.name == definition.name) {
+                None => diff.added.push(definition.clone()),
+                Some(previous) => {
+                    let fields = changed_fields(previous, definition);
+                    match fields.is_empty() {
+                        true => diff.unchanged += 1,
+                        false => diff.changed.push(ChangedProcess {
+                            definition: definition.clone(),
+                            fields,
+                        }),
+                    }
+                }
+            }
+        }
+
+        diff.removed = old
+            .iter()
+            .filter(|o| new.iter().all(|n| n.name != o.name))
+            .map(|o| o.name.clone())
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn changed_fields(old: &ProcessDefinition, new: &ProcessDefinition) -> Vec<&'static str> {
+    let mut fields = vec![];
+    if old.launch != new.launch {
+        fields.push("command");
+    }
+    if old.cwd != new.cwd {
+        fields.push("cwd");
+    }
+    if old.environment != new.environment {
+        fields.push("env");
+    }
+    if old.auto_start != new.auto_start {
+        fields.push("auto_start");
+    }
+    if old.depends_on != new.depends_on {
+        fields.push("depends_on");
+    }
+    if old.readiness != new.readiness {
+        fields.push("ready");
+    }
+    if old.restart != new.restart {
+        fields.push("restart");
+    }
+    if old.stop != new.stop {
+        fields.push("stop");
+    }
+    if old.scrollback != new.scrollback {
+        fields.push("scrollback");
+    }
+    if old.strip_color != new.strip_color {
+        fields.push("strip_color");
+    }
+    if old.encoding != new.encoding {
+        fields.push("encoding");
+    }
+    if old.tty != new.tty {
+        fields.push("tty");
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn names(definitions: &[ProcessDefinition]) -> Vec<&str> {
+        definitions.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn same_config_is_empty() {
+        let processes = [
+            ProcessDefinition::named("web"),
+            ProcessDefinition::named("db"),
+        ];
+        let diff = ConfigDiff::new(&processes, &processes);
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, 2);
+    }
+
+    #[test]
+    fn added_and_removed_are_matched_by_name() {
+        let diff = ConfigDiff::new(
+            &[
+                ProcessDefinition::named("web"),
+                ProcessDefinition::named("db"),
+            ],
+            &[
+                ProcessDefinition::named("web"),
+                ProcessDefinition::named("api"),
+            ],
+        );
+        assert_eq!(names(&diff.added), ["api"]);
+        assert_eq!(diff.removed, ["db"]);
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn live_only_changes_need_no_restart() {
+        let mut web = ProcessDefinition::named("web");
+        web.auto_start = true;
+        web.depends_on = vec!["db".to_string()];
+        web.strip_color = true;
+
+        let diff = ConfigDiff::new(&[ProcessDefinition::named("web")], &[web]);
+        let [changed] = &diff.changed[..] else {
+            panic!("expected one changed process, got {:?}", diff.changed);
+        };
+        assert_eq!(changed.fields, ["auto_start", "depends_on", "strip_color"]);
+        assert!(!changed.needs_restart());
+    }
+
+    #[test]
+    fn other_changes_need_a_restart() {
+        let mut web = ProcessDefinition::named("web");
+        web.auto_start = true;
+        web.cwd = PathBuf::from("/elsewhere");
+        web.environment.vars = vec![("PORT".to_string(), "3000".to_string())];
+
+        let diff = ConfigDiff::new(&[ProcessDefinition::named("web")], &[web]);
+        let [changed] = &diff.changed[..] else {
+            panic!("expected one changed process, got {:?}", diff.changed);
+        };
+        assert_eq!(changed.fields, ["cwd", "env", "auto_start"]);
+        assert!(changed.needs_restart());
+    }
+}
@@ -0,0 +1,257 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use super::diagnostic::{Diagnostics, Location};
+use crate::MultiHostError;
+
+/// How a hosted process's environment is built when it is spawned.
+///
+/// Layers are applied in order: the inherited environment (or only the
+/// `inherit` allow-list when `clear` is set), then each env file, then the
+/// `vars` from the config, and finally the `path_prepend` entries on `PATH`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    pub vars: Vec<(String, String)>,
+    pub files: Vec<PathBuf>,
+    pub clear: bool,
+    pub inherit: Vec<String>,
+    pub path_prepend: Vec<PathBuf>,
+}
+
+impl Environment {
+    /// Env files are read here rather than at load time, so edits to them are
+    /// picked up the next time the process starts.
+    pub fn apply(&self, cmd: &mut Command) -> Result<(), MultiHostError> {
+        if self.clear {
+            cmd.env_clear();
+            for name in &self.inherit {
+                if let Some(value) = env::var_os(name) {
+                    cmd.env(name, value);
+                }
+            }
+        }
+
+        for file in &self.files {
+            for (name, value) in read_env_file(file)? {
+                cmd.env(name, value);
+            }
+        }
+
+        for (name, value) in &self.vars {
+            cmd.env(name, value);
+        }
+
+        if !self.path_prepend.is_empty() {
+            let current = cmd
+                .get_envs()
+                .find(|(name, _)| *name == "PATH")
+                .map(|(_, value)| value.map(OsString::from))
+                .unwrap_or_else(|| match self.clear {
+                    true => None,
+                    false => env::var_os("PATH"),
+                });
+
+            let mut paths = self.path_prepend.clone();
+            if let Some(current) = current {
+                paths.extend(env::split_paths(&current));
+            }
+            let joined = env::join_paths(paths)
+                .map_err(|e| MultiHostError::Simple(format!("couldn't build PATH: {}", e)))?;
+            cmd.env("PATH", joined);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a dotenv file: `KEY=value` lines, optionally prefixed with `export`,
+/// with `#` comments and single- or double-quoted values.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, MultiHostError> {
+    let source = fs::read_to_string(path).map_err(|source| MultiHostError::EnvFileRead {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let file: Arc<Path> = Arc::from(path);
+    let mut diagnostics = Diagnostics::default();
+    let mut vars = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let location = Location {
+            file: Arc::clone(&file),
+            line: index + 1,
+            column: line.len() - trimmed.len() + 1,
+        };
+
+        let entry = trimmed.strip_prefix("export ").unwrap_or(trimmed);
+        let Some((name, value)) = entry.split_once('=') else {
+            diagnostics.error(&location, "expected `KEY=value`");
+            continue;
+        };
+
+        let name = name.trim();
+        if !is_valid_name(name) {
+            diagnostics.error(&location, format!("invalid variable name `{}`", name));
+            continue;
+        }
+
+        match parse_value(value.trim()) {
+            Ok(value) => vars.push((name.to_string(), value)),
+            Err(message) => diagnostics.error(&location, message),
+        }
+    }
+
+    match diagnostics.is_empty() {
+        true => Ok(vars),
+        false => Err(MultiHostError::EnvFile(diagnostics)),
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        return match rest.split_once('\'') {
+            Some((inner, trailing)) if is_comment_or_empty(trailing) => Ok(inner.to_string()),
+            Some(_) => Err("unexpected text after closing quote".to_string()),
+            None => Err("missing closing `'`".to_string()),
+        };
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut parsed = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    return match is_comment_or_empty(chars.as_str()) {
+                        true => Ok(parsed),
+                        false => Err("unexpected text after closing quote".to_string()),
+                    };
+                }
+                '\\' => match chars.next() {
+                    Some('n') => parsed.push('\n'),
+                    Some('t') => parsed.push('\t'),
+                    Some(other) => parsed.push(other),
+                    None => break,
+                },
+                _ => parsed.push(c),
+            }
+        }
+        return Err("missing closing `\"`".to_string());
+    }
+
+    // Unquoted values end at an inline comment
+    let value = match value.find(" #") {
+        Some(index) => &value[..index],
+        None => value,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+fn is_comment_or_empty(text: &str) -> bool {
+    let text = text.trim();
+    text.is_empty() || text.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquoted_values() {
+        assert_eq!(parse_value("plain"), Ok("plain".to_string()));
+        assert_eq!(parse_value("two words"), Ok("two words".to_string()));
+        assert_eq!(parse_value("value # comment"), Ok("value".to_string()));
+        assert_eq!(parse_value("a#b"), Ok("a#b".to_string()));
+        assert_eq!(parse_value(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(
+            parse_value(r"'a \n $b # c'"),
+            Ok(r"a \n $b # c".to_string())
+        );
+        assert_eq!(parse_value("'quoted' # comment"), Ok("quoted".to_string()));
+        assert_eq!(
+            parse_value("'quoted' extra"),
+            Err("unexpected text after closing quote".to_string())
+        );
+        assert_eq!(parse_value("'open"), Err("missing closing `'`".to_string()));
+    }
+
+    #[test]
+    fn double_quotes_take_escapes() {
+        assert_eq!(
+            parse_value(r#""line\nnext\ttab \"q\" \\ # not a comment""#),
+            Ok("line\nnext\ttab \"q\" \\ # not a comment".to_string())
+        );
+        assert_eq!(parse_value(r#""x" # comment"#), Ok("x".to_string()));
+        assert_eq!(
+            parse_value(r#""x"y"#),
+            Err("unexpected text after closing quote".to_string())
+        );
+        assert_eq!(
+            parse_value(r#""open\""#),
+            Err("missing closing `\"`".to_string())
+        );
+    }
+
+    #[test]
+    fn env_file_problems_are_located() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(".env");
+        fs::write(
+            &path,
+            "# comment\nexport A=1\n  B='two'\n1X=bad\nnothing\nC=\"open\n",
+        )
+        .unwrap();
+
+        let Err(MultiHostError::EnvFile(diagnostics)) = read_env_file(&path) else {
+            panic!("expected the env file to be rejected");
+        };
+        let file = path.display();
+        assert_eq!(
+            diagnostics.to_string(),
+            format!(
+                "{file}:4:1: invalid variable name `1X`\n\
+                 {file}:5:1: expected `KEY=value`\n\
+                 {file}:6:1: missing closing `\"`"
+            )
+        );
+    }
+
+    #[test]
+    fn env_file_values() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(".env");
+        fs::write(
+            &path,
+            "# comment\nexport A=1\n  B='two'\nC = \"3\" # three\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            [
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two".to_string()),
+                ("C".to_string(), "3".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Everything a `${...}` reference can resolve to while one process's
+/// definition is being decoded.
+///
+/// - `${VAR}` and `${VAR:-default}` read multi-host's own environment
+/// - `${config_dir}` is the directory of the file the value is written in
+/// - `${process.name}` and `${process.<var>}` refer to the current process
+/// - `${<name>.name}` and `${<name>.<var>}` refer to another process
+///
+/// `<var>` is a key from that process's `vars:` mapping. `$$` is a literal `$`.
+pub struct Scope<'a> {
+    pub process: &'a str,
+    pub vars: &'a HashMap<String, HashMap<String, String>>,
+}
+
+impl Scope<'_> {
+    pub fn expand(&self, text: &str, config_dir: &Path) -> Result<String, String> {
+        expand(text, |reference| self.lookup(reference, config_dir))
+    }
+
+    fn lookup(&self, reference: &str, config_dir: &Path) -> Option<Result<String, String>> {
+        if reference == "config_dir" {
+            return Some(Ok(absolute(config_dir).display().to_string()));
+        }
+
+        let (process, key) = reference.split_once('.')?;
+        let process = match process {
+            "process" => self.process,
+            other => other,
+        };
+        if key == "name" && self.vars.contains_key(process) {
+            return Some(Ok(process.to_string()));
+        }
+
+        Some(match self.vars.get(process) {
+            None => Err(format!(
+                "unknown process `{}` in `${{{}}}`",
+                process, reference
+            )),
+            Some(vars) => vars
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("process `{}` has no var `{}`", process, key)),
+        })
+    }
+}
+
+/// Expands references in a `vars:` value. These can use the environment and
+/// `${config_dir}`, but not other vars, so there is no evaluation order to
+/// worry about.
+pub fn expand_var(text: &str, process: &str, config_dir: &Path) -> Result<String, String> {
+    expand(text, |reference| match reference {
+        "config_dir" => Some(Ok(absolute(config_dir).display().to_string())),
+        "process.name" => Some(Ok(process.to_string())),
+        _ if reference.contains('.') => Some(Err(format!(
+            "`${{{}}}` can't be used in `vars`, only environment variables and `${{config_dir}}`",
+            reference
+        ))),
+        _ => None,
+    })
+}
+
+/// Expands `${...}` references. `lookup` handles the special names and
+/// returns `None` to fall back to the environment.
+fn expand(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<Result<String, String>>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix("${") else {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(end) = after.find('}') else {
+            return Err(format!("unterminated `${{` in `{}`", text));
+        };
+
+        let body = &after[..end];
+        let (reference, default) = match body.split_once(":-") {
+            Some((reference, default)) => (reference, Some(default)),
+            None => (body, None),
+        };
+        if reference.is_empty() {
+            return Err(format!("empty `${{}}` in `{}`", text));
+        }
+
+        let value = match lookup(reference) {
+            Some(value) => value?,
+            None => match (env::var(reference), default) {
+                (Ok(value), Some(default)) if value.is_empty() => default.to_string(),
+                (Ok(value), _) => value,
+                (Err(_), Some(default)) => default.to_string(),
+                (Err(_), None) => {
+                    return Err(format!("environment variable `{}` is not set", reference));
+                }
+            },
+        };
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Resolves a path written in a config file: `~` is the home directory and
+/// relative paths are relative to the directory of that file.
+pub fn resolve_path(path: &str, config_dir: &Path) -> PathBuf {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => home().map(|home| home.join(rest)),
+        None if path == "~" => home(),
+        None => None,
+    }
+    .unwrap_or_else(|| PathBuf::from(path));
+
+    absolute(&config_dir.join(path))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // always set, so the tests needn't change the environment
+    const SET: &str = "PATH";
+    const UNSET: &str = "MULTI_HOST_TEST_SURELY_UNSET";
+
+    fn vars() -> HashMap<String, HashMap<String, String>> {
+        HashMap::from([
+            (
+                "web".to_string(),
+                HashMap::from([("port".to_string(), "3000".to_string())]),
+            ),
+            ("db".to_string(), HashMap::new()),
+        ])
+    }
+
+    fn expand_in(process: &str, text: &str) -> Result<String, String> {
+        let vars = vars();
+        let scope = Scope {
+            process,
+            vars: &vars,
+        };
+        scope.expand(text, Path::new("/work"))
+    }
+
+    #[test]
+    fn environment_variables_and_defaults() {
+        let value = env::var(SET).unwrap();
+        assert_eq!(
+            expand_in("web", &format!("${{{}}}", SET)),
+            Ok(value.clone())
+        );
+        assert_eq!(
+            expand_in("web", &format!("${{{}:-fallback}}", SET)),
+            Ok(value)
+        );
+        assert_eq!(
+            expand_in("web", &format!("a-${{{}:-fallback}}-b", UNSET)),
+            Ok("a-fallback-b".to_string())
+        );
+        assert_eq!(
+            expand_in("web", &format!("${{{}:-}}", UNSET)),
+            Ok(String::new())
+        );
+        assert_eq!(
+            expand_in("web", &format!("${{{}}}", UNSET)),
+            Err(format!("environment variable `{}` is not set", UNSET))
+        );
+    }
+
+    #[test]
+    fn dollars() {
+        assert_eq!(expand_in("web", "cost: $$5"), Ok("cost: $5".to_string()));
+        assert_eq!(expand_in("web", "$$$${x}"), Ok("$${x}".to_string()));
+        assert_eq!(expand_in("web", "$HOME $"), Ok("$HOME $".to_string()));
+    }
+
+    #[test]
+    fn malformed_references() {
+        assert_eq!(
+            expand_in("web", "${oops"),
+            Err("unterminated `${` in `${oops`".to_string())
+        );
+        assert_eq!(
+            expand_in("web", "${}"),
+            Err("empty `${}` in `${}`".to_string())
+        );
+    }
+
+    #[test]
+    fn process_references() {
+        assert_eq!(
+            expand_in("web", "${process.name}:${process.port}"),
+            Ok("web:3000".to_string())
+        );
+        assert_eq!(
+            expand_in("db", "${web.name} on ${web.port}"),
+            Ok("web on 3000".to_string())
+        );
+        assert_eq!(
+            expand_in("db", "${process.port}"),
+            Err("process `db` has no var `port`".to_string())
+        );
+        assert_eq!(
+            expand_in("web", "${api.port}"),
+            Err("unknown process `api` in `${api.port}`".to_string())
+        );
+        assert_eq!(
+            expand_in("web", "${config_dir}/data"),
+            Ok("/work/data".to_string())
+        );
+    }
+
+    #[test]
+    fn vars_only_see_the_environment_and_config_dir() {
+        assert_eq!(
+            expand_var("${config_dir}/${process.name}", "web", Path::new("/work")),
+            Ok("/work/web".to_string())
+        );
+        assert_eq!(
+            expand_var("${web.port}", "web", Path::new("/work")),
+            Err(
+                "`${web.port}` can't be used in `vars`, only environment variables and `${config_dir}`"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn paths_are_resolved_against_the_config_dir() {
+        assert_eq!(
+            resolve_path("data/db", Path::new("/work")),
+            PathBuf::from("/work/data/db")
+        );
+        assert_eq!(
+            resolve_path("/tmp", Path::new("/work")),
+            PathBuf::from("/tmp")
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+
+/// How a hosted process is launched: either an executable with its own
+/// argument list, or a command line handed to a shell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Launch {
+    Exe {
+        exe: String,
+        args: Vec<String>,
+    },
+    Shell {
+        /// The shell and the flags that make it run a command string,
+        /// e.g. `["sh", "-c"]`.
+        program: Vec<String>,
+        script: String,
+    },
+}
+
+/// The shell used for `shell:` processes unless `shell_program` says otherwise.
+pub fn default_shell_program() -> Vec<String> {
+    let program: &[&str] = match cfg!(windows) {
+        true => &["cmd", "/C"],
+        false => &["sh", "-c"],
+    };
+    program.iter().map(|part| part.to_string()).collect()
+}
+
+impl Launch {
+    pub fn program(&self) -> &str {
+        match self {
+            Launch::Exe { exe, .. } => exe,
+            Launch::Shell { program, .. } => &program[0],
+        }
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Launch::Exe { args, .. } => args.clone(),
+            Launch::Shell { program, script } => {
+                let mut args = program[1..].to_vec();
+                args.push(script.clone());
+                args
+            }
+        }
+    }
+
+    /// The command line that actually gets run, quoted the way a POSIX
+    /// shell would need it.
+    pub fn command_line(&self) -> String {
+        let mut parts = vec![quote(self.program())];
+        let args = self.args();
+        parts.extend(args.iter().map(|arg| quote(arg)));
+        parts.join(" ")
+    }
+}
+
+fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    match safe {
+        true => Cow::Borrowed(arg),
+        false => Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exe(exe: &str, args: &[&str]) -> Launch {
+        Launch::Exe {
+            exe: exe.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    fn shell(program: &[&str], script: &str) -> Launch {
+        Launch::Shell {
+            program: program.iter().map(|part| part.to_string()).collect(),
+            script: script.to_string(),
+        }
+    }
+
+    #[test]
+    fn plain_args_are_left_alone() {
+        assert_eq!(
+            exe("npm", &["run", "dev", "--port=3000", "./src/a.js"]).command_line(),
+            "npm run dev --port=3000 ./src/a.js"
+        );
+    }
+
+    #[test]
+    fn args_are_quoted_when_they_need_it() {
+        assert_eq!(
+            exe("/opt/my tools/run", &["two words", "it's", "", "$HOME"]).command_line(),
+            r"'/opt/my tools/run' 'two words' 'it'\''s' '' '$HOME'"
+        );
+    }
+
+    #[test]
+    fn shell_commands_go_to_the_shell_as_one_arg() {
+        let launch = shell(&["sh", "-c"], "npm run dev && echo 'done'");
+        assert_eq!(launch.program(), "sh");
+        assert_eq!(launch.args(), ["-c", "npm run dev && echo 'done'"]);
+        assert_eq!(
+            launch.command_line(),
+            r"sh -c 'npm run dev && echo '\''done'\'''"
+        );
+
+        let launch = shell(&["cmd", "/C"], "npm run dev");
+        assert_eq!(launch.program(), "cmd");
+        assert_eq!(launch.args(), ["/C", "npm run dev"]);
+        assert_eq!(launch.command_line(), "cmd /C 'npm run dev'");
+    }
+
+    #[test]
+    fn default_shell_takes_a_command_string() {
+        let expected = match cfg!(windows) {
+            true => ["cmd", "/C"],
+            false => ["sh", "-c"],
+        };
+        assert_eq!(default_shell_program(), expected);
+    }
+}
@@ -0,0 +1,437 @@
+//! Builds the final config tree out of the main file, everything it
+//! includes, and the optional `<name>.local.yaml` override next to it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::diagnostic::{Diagnostics, Location};
+use super::yaml::{self, Node, Value};
+use crate::MultiHostError;
+
+/// Keys an included file may contain. Everything else belongs to the main file.
+const INCLUDABLE_KEYS: [&str; 3] = ["include", "anchors", "process"];
+
+pub struct Layers {
+    pub root: Option<Node>,
+    /// Every file that contributed to the config, plus the local override
+    /// path even when it doesn't exist yet, so it can be watched.
+    pub sources: Vec<PathBuf>,
+}
+
+/// The personal override file for `path`: `multi-host.yaml` becomes
+/// `multi-host.local.yaml`.
+pub fn local_override_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.local.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.local", stem),
+    };
+    path.with_file_name(file_name)
+}
+
+pub fn assemble(path: &Path, diagnostics: &mut Diagnostics) -> Result<Layers, MultiHostError> {
+    let source = fs::read_to_string(path).map_err(|source| MultiHostError::ConfigRead {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut sources = vec![path.to_path_buf()];
+    let Some(mut root) = parse(path, &source, diagnostics) else {
+        return Ok(Layers {
+            root: None,
+            sources,
+        });
+    };
+
+    let mut stack = vec![canonical(path)];
+    resolve_includes(&mut root, &mut stack, &mut sources, diagnostics);
+
+    // The override is optional, but one that is there and can't be read
+    // mustn't be left out without a word
+    let local_path = local_override_path(path);
+    match fs::read_to_string(&local_path) {
+        Ok(source) => {
+            if let Some(overlay) = parse(&local_path, &source, diagnostics) {
+                apply_override(&mut root, overlay, diagnostics);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            let location = Location {
+                file: Arc::from(local_path.as_path()),
+                line: 1,
+                column: 1,
+            };
+            diagnostics.error(
+                &location,
+                format!(
+                    "could not read local override `{}`: {}",
+                    local_path.display(),
+                    e
+                ),
+            );
+        }
+    }
+    sources.push(local_path);
+
+    Ok(Layers {
+        root: Some(root),
+        sources,
+    })
+}
+
+fn parse(path: &Path, source: &str, diagnostics: &mut Diagnostics) -> Option<Node> {
+    match yaml::parse(Arc::from(path), source) {
+        Ok(node) => node,
+        Err(errors) => {
+            diagnostics.append(errors);
+            None
+        }
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Replaces the `include:` list in `node` with the processes of the included
+/// files. Included processes come before the file's own ones.
+fn resolve_includes(
+    node: &mut Node,
+    stack: &mut Vec<PathBuf>,
+    sources: &mut Vec<PathBuf>,
+    diagnostics: &mut Diagnostics,
+) {
+    let Some(include) = node.remove("include") else {
+        return;
+    };
+    let Some(entries) = include.as_sequence() else {
+        diagnostics.error(
+            &include.location,
+            format!(
+                "`include` should be a list of files, found {}",
+                include.kind()
+            ),
+        );
+        return;
+    };
+
+    let mut included_processes = vec![];
+    for entry in entries {
+        let Some(relative) = entry.as_str() else {
+            diagnostics.error(
+                &entry.location,
+                format!(
+                    "`include` entries should be file paths, found {}",
+                    entry.kind()
+                ),
+            );
+            continue;
+        };
+
+        let path = entry.location.directory().join(relative);
+        let key = canonical(&path);
+        if stack.contains(&key) {
+            let cycle: Vec<String> = stack
+                .iter()
+                .chain([&key])
+                .map(|p| p.display().to_string())
+                .collect();
+            diagnostics.error(
+                &entry.location,
+                format!("include cycle: {}", cycle.join(" -> ")),
+            );
+            continue;
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                diagnostics.error(
+                    &entry.location,
+                    format!("could not read included file `{}`: {}", path.display(), e),
+                );
+                continue;
+            }
+        };
+        sources.push(path.clone());
+
+        let Some(mut included) = parse(&path, &source, diagnostics) else {
+            continue;
+        };
+        if let Some(entries) = included.as_mapping() {
+            for (key, _) in entries {
+                if !INCLUDABLE_KEYS.contains(&key.as_str().unwrap_or_default()) {
+                    diagnostics.error(
+                        &key.location,
+                        format!(
+                            "`{}` can only be set in the main config file",
+                            key.as_str().unwrap_or("?")
+                        ),
+                    );
+                }
+            }
+        }
+
+        stack.push(key);
+        resolve_includes(&mut included, stack, sources, diagnostics);
+        stack.pop();
+
+        if let Some(list) = included.remove("process") {
+            match list.value {
+                Value::Sequence(items) => included_processes.extend(items),
+                _ => diagnostics.error(
+                    &list.location,
+                    format!("`process` should be a list, found {}", list.kind()),
+                ),
+            }
+        }
+    }
+
+    if included_processes.is_empty() {
+        return;
+    }
+    let location = node.location.clone();
+    let list = node.entry("process", || Node {
+        value: Value::Sequence(vec![]),
+        location,
+    });
+    if let Value::Sequence(items) = &mut list.value {
+        included_processes.append(items);
+        *items = included_processes;
+    }
+}
+
+/// Deep-merges the local override over the assembled config. Processes are
+/// matched by name; an override can change them but not add new ones.
+fn apply_override(root: &mut Node, overlay: Node, diagnostics: &mut Diagnostics) {
+    let Value::Mapping(entries) = overlay.value else {
+        diagnostics.error(
+            &overlay.location,
+            format!(
+                "local override should be a mapping, found {}",
+                overlay.kind()
+            ),
+        );
+        return;
+    };
+
+    for (key, value) in entries {
+        match key.as_str() {
+            Some("anchors") => (),
+            Some("include") => {
+                diagnostics.error(&key.location, "`include` can't be used in a local override")
+            }
+            Some("process") => override_processes(root, value, diagnostics),
+            Some(name) => match root.get_mut(name) {
+                Some(base) => merge(base, value, name, diagnostics),
+                None => root.insert(key, value),
+            },
+            None => diagnostics.error(&key.location, "override keys should be strings"),
+        }
+    }
+}
+
+fn override_processes(root: &mut Node, overrides: Node, diagnostics: &mut Diagnostics) {
+    let Value::Sequence(overrides) = overrides.value else {
+        diagnostics.error(
+            &overrides.location,
+            format!("`process` should be a list, found {}", overrides.kind()),
+        );
+        return;
+    };
+
+    for process in overrides {
+        let Some(name) = process
+            .get("name")
+            .and_then(Node::as_str)
+            .map(str::to_string)
+        else {
+            diagnostics.error(
+                &process.location,
+                "process overrides need a `name` to say which process they change",
+            );
+            continue;
+        };
+
+        let base = root
+            .get_mut("process")
+            .and_then(|list| match &mut list.value {
+                Value::Sequence(items) => items
+                    .iter_mut()
+                    .find(|item| item.get("name").and_then(Node::as_str) == Some(&name)),
+                _ => None,
+            });
+
+        match base {
+            Some(base) => merge(base, process, &name, diagnostics),
+            None => diagnostics.error(
+                &process.location,
+                format!("override for unknown process `{}`", name),
+            ),
+        }
+    }
+}
+
+/// Mappings merge key by key; anything else in the overlay replaces the base
+/// value outright. A mapping on one side and something else on the other is
+/// a conflict.
+fn merge(base: &mut Node, overlay: Node, path: &str, diagnostics: &mut Diagnostics) {
+    let base_is_mapping = base.as_mapping().is_some();
+    match overlay.value {
+        Value::Mapping(entries) if base_is_mapping => {
+            for (key, value) in entries {
+                let Some(name) = key.as_str().map(str::to_string) else {
+                    diagnostics.error(&key.location, "override keys should be strings");
+                    continue;
+                };
+                let child_path = format!("{}.{}", path, name);
+                match base.get_mut(&name) {
+                    Some(existing) => merge(existing, value, &child_path, diagnostics),
+                    None => base.insert(key, value),
+                }
+            }
+        }
+        _ if base_is_mapping || overlay.as_mapping().is_some() => diagnostics.error(
+            &overlay.location,
+            format!(
+                "conflicting override for `{}`: it is {} here but {} at {}",
+                path,
+                overlay.kind(),
+                base.kind(),
+                base.location
+            ),
+        ),
+        _ => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_file(name: &str, source: &str) -> Node {
+        yaml::parse(Arc::from(Path::new(name)), source)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn overridden(base: &str, local: &str) -> (Node, Diagnostics) {
+        let mut root = parse_file("multi-host.yaml", base);
+        let mut diagnostics = Diagnostics::default();
+        apply_override(
+            &mut root,
+            parse_file("multi-host.local.yaml", local),
+            &mut diagnostics,
+        );
+        (root, diagnostics)
+    }
+
+    fn text<'a>(node: &'a Node, path: &[&str]) -> Option<&'a str> {
+        path.iter()
+            .try_fold(node, |node, key| node.get(key))
+            .and_then(Node::as_str)
+    }
+
+    const BASE: &str = "\
+reload:
+  watch: true
+  restart_changed: false
+process:
+  - name: web
+    exe: npm
+    cwd: web
+    env:
+      PORT: 3000
+      HOST: localhost
+  - name: db
+    exe: postgres
+    cwd: .
+";
+
+    #[test]
+    fn mappings_merge_key_by_key() {
+        let (root, diagnostics) = overridden(
+            BASE,
+            "\
+reload:
+  watch: false
+process:
+  - name: web
+    env:
+      PORT: 4000
+      DEBUG: 1
+",
+        );
+        assert!(diagnostics.is_empty(), "{}", diagnostics);
+        assert_eq!(text(&root, &["reload", "watch"]), Some("false"));
+        assert_eq!(text(&root, &["reload", "restart_changed"]), Some("false"));
+
+        let web = &root.get("process").unwrap().as_sequence().unwrap()[0];
+        assert_eq!(text(web, &["exe"]), Some("npm"));
+        assert_eq!(text(web, &["cwd"]), Some("web"));
+        assert_eq!(text(web, &["env", "PORT"]), Some("4000"));
+        assert_eq!(text(web, &["env", "HOST"]), Some("localhost"));
+        assert_eq!(text(web, &["env", "DEBUG"]), Some("1"));
+    }
+
+    #[test]
+    fn lists_and_scalars_are_replaced() {
+        let (root, diagnostics) = overridden(
+            "process:\n  - name: web\n    exe: npm\n    cwd: .\n    args: [a, b]\n",
+            "process:\n  - name: web\n    args: [c]\n",
+        );
+        assert!(diagnostics.is_empty(), "{}", diagnostics);
+        let web = &root.get("process").unwrap().as_sequence().unwrap()[0];
+        let args = web.get("args").unwrap().as_sequence().unwrap();
+        assert_eq!(args.len(), 1);
+        assert_eq!(args[0].as_str(), Some("c"));
+    }
+
+    #[test]
+    fn conflicts_are_reported_with_both_locations() {
+        let (_, diagnostics) = overridden(BASE, "process:\n  - name: web\n    env: PORT=4000\n");
+        assert_eq!(
+            diagnostics.to_string(),
+            "multi-host.local.yaml:3:10: conflicting override for `web.env`: \
+             it is a scalar here but a mapping at multi-host.yaml:9:11"
+        );
+    }
+
+    #[test]
+    fn unknown_processes_are_reported() {
+        let (root, diagnostics) = overridden(
+            BASE,
+            "process:\n  - name: api\n    exe: ./api\n  - exe: ./nameless\n",
+        );
+        assert_eq!(
+            diagnostics.to_string(),
+            "multi-host.local.yaml:2:9: override for unknown process `api`\n\
+             multi-host.local.yaml:4:8: process overrides need a `name` to say which process they change"
+        );
+        assert_eq!(root.get("process").unwrap().as_sequence().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn include_is_rejected() {
+        let (_, diagnostics) = overridden(BASE, "include: [other.yaml]\n");
+        assert_eq!(
+            diagnostics.to_string(),
+            "multi-host.local.yaml:1:1: `include` can't be used in a local override"
+        );
+    }
+
+    #[test]
+    fn local_override_path_goes_next_to_the_config() {
+        assert_eq!(
+            local_override_path(Path::new("/work/multi-host.yaml")),
+            PathBuf::from("/work/multi-host.local.yaml")
+        );
+        assert_eq!(
+            local_override_path(Path::new("/work/multi-host")),
+            PathBuf::from("/work/multi-host.local")
+        );
+    }
+}
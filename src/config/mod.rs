@@ -0,0 +1,1348 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::MultiHostError;
+use diagnostic::{Diagnostics, Location};
+use encoding_rs::{Encoding, UTF_8};
+use environment::Environment;
+use interpolate::Scope;
+use launch::Launch;
+use readiness::{HttpTarget, Readiness, ReadinessCheck};
+use regex::Regex;
+use restart::{RestartPolicy, RestartWhen};
+use scrollback::{ScrollbackLimits, ScrollbackSettings};
+use stop::{StopSettings, StopSignal};
+use tty::{TtySize, WindowSize};
+use yaml::Node;
+
+pub mod dependencies;
+pub mod diagnostic;
+pub mod diff;
+pub mod environment;
+mod interpolate;
+pub mod launch;
+mod layers;
+pub mod readiness;
+pub mod restart;
+pub mod scrollback;
+pub mod stop;
+pub mod tty;
+mod watch;
+mod yaml;
+
+pub use watch::watch;
+
+#[derive(Debug)]
+pub struct Configuration {
+    pub processes: Rc<Vec<ProcessDefinition>>,
+    pub reload: ReloadSettings,
+    pub scrollback: ScrollbackSettings,
+    /// The files the config was assembled from, for the reload watcher.
+    pub sources: Vec<PathBuf>,
+}
+
+/// The `reload:` section, controlling what happens when the config file
+/// changes while multi-host is running.
+#[derive(Debug, Clone)]
+pub struct ReloadSettings {
+    pub watch: bool,
+    pub restart_changed: bool,
+}
+
+impl Default for ReloadSettings {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            restart_changed: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessDefinition {
+    pub name: String,
+    pub launch: Launch,
+    pub cwd: PathBuf,
+    pub auto_start: bool,
+    /// Names of the processes that have to be started before this one.
+    pub depends_on: Vec<String>,
+    pub readiness: Option<Readiness>,
+    pub restart: RestartPolicy,
+    pub stop: StopSettings,
+    pub environment: Environment,
+    pub scrollback: ScrollbackLimits,
+    /// Show output without the colours and styles its escape sequences set.
+    pub strip_color: bool,
+    /// What the process writes its output in.
+    pub encoding: &'static Encoding,
+    /// Run under a pseudo-terminal of this size instead of with pipes.
+    pub tty: Option<TtySize>,
+}
+
+#[cfg(test)]
+impl ProcessDefinition {
+    /// A process running `name` with every setting at its default.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            launch: Launch::Exe {
+                exe: name.to_string(),
+                args: vec![],
+            },
+            cwd: PathBuf::from("/work"),
+            auto_start: false,
+            depends_on: vec![],
+            readiness: None,
+            restart: RestartPolicy::default(),
+            stop: StopSettings::default(),
+            environment: Environment::default(),
+            scrollback: ScrollbackSettings::default().process,
+            strip_color: false,
+            encoding: UTF_8,
+            tty: None,
+        }
+    }
+}
+
+/// Reads and validates the config file at `path`, along with the files it
+/// includes and its local override. Every problem is reported at once, each
+/// with its file, line and column.
+pub fn load(path: &Path) -> Result<Configuration, MultiHostError> {
+    let mut diagnostics = Diagnostics::default();
+    let layers = layers::assemble(path, &mut diagnostics)?;
+
+    let (processes, reload, scrollback) = match layers.root {
+        Some(root) => decode_root(&root, &mut diagnostics),
+        None => {
+            if diagnostics.is_empty() {
+                let location = Location {
+                    file: Arc::from(path),
+                    line: 1,
+                    column: 1,
+                };
+                diagnostics.error(&location, "config file is empty");
+            }
+            (
+                vec![],
+                ReloadSettings::default(),
+                ScrollbackSettings::default(),
+            )
+        }
+    };
+
+    match diagnostics.is_empty() {
+        true => Ok(Configuration {
+            processes: Rc::new(processes),
+            reload,
+            scrollback,
+            sources: layers.sources,
+        }),
+        false => Err(diagnostics.into()),
+    }
+}
+
+fn decode_root(
+    root: &Node,
+    diagnostics: &mut Diagnostics,
+) -> (Vec<ProcessDefinition>, ReloadSettings, ScrollbackSettings) {
+    let Some(mut fields) = Fields::new(root, "config file", diagnostics) else {
+        return (
+            vec![],
+            ReloadSettings::default(),
+            ScrollbackSettings::default(),
+        );
+    };
+
+    // `anchors` is free-form: it only exists to hold YAML anchors for reuse
+    fields.get("anchors");
+
+    let shell_program = fields
+        .shell_program(diagnostics)
+        .unwrap_or_else(launch::default_shell_program);
+
+    let scrollback = match fields.get("scrollback") {
+        Some(node) => decode_scrollback(node, diagnostics),
+        None => ScrollbackSettings::default(),
+    };
+
+    let processes = match fields.get("process") {
+        Some(list) => decode_processes(list, &shell_program, scrollback.process, diagnostics),
+        None => {
+            diagnostics.error(&root.location, "missing required key `process`");
+            vec![]
+        }
+    };
+
+    let reload = match fields.get("reload") {
+        Some(node) => decode_reload(node, diagnostics),
+        None => ReloadSettings::default(),
+    };
+
+    fields.finish(diagnostics);
+    (processes, reload, scrollback)
+}
+
+fn decode_reload(node: &Node, diagnostics: &mut Diagnostics) -> ReloadSettings {
+    let defaults = ReloadSettings::default();
+    let Some(mut fields) = Fields::new(node, "`reload`", diagnostics) else {
+        return defaults;
+    };
+
+    let settings = ReloadSettings {
+        watch: fields.bool("watch", diagnostics).unwrap_or(defaults.watch),
+        restart_changed: fields
+            .bool("restart_changed", diagnostics)
+            .unwrap_or(defaults.restart_changed),
+    };
+
+    fields.finish(diagnostics);
+    settings
+}
+
+/// The top level `scrollback:` sets the default for each process, with
+/// `total_lines` and `total_bytes` for all of them together.
+fn decode_scrollback(node: &Node, diagnostics: &mut Diagnostics) -> ScrollbackSettings {
+    let mut settings = ScrollbackSettings::default();
+    let Some(mut fields) = Fields::new(node, "`scrollback`", diagnostics) else {
+        return settings;
+    };
+
+    settings.process = decode_scrollback_limits(&mut fields, "", settings.process, diagnostics);
+    settings.total = decode_scrollback_limits(&mut fields, "total_", settings.total, diagnostics);
+
+    fields.finish(diagnostics);
+    settings
+}
+
+/// A process's own `scrollback:`, falling back to the top level one.
+fn decode_process_scrollback(
+    node: &Node,
+    defaults: ScrollbackLimits,
+    diagnostics: &mut Diagnostics,
+) -> ScrollbackLimits {
+    let Some(mut fields) = Fields::new(node, "`scrollback`", diagnostics) else {
+        return defaults;
+    };
+    let limits = decode_scrollback_limits(&mut fields, "", defaults, diagnostics);
+    fields.finish(diagnostics);
+    limits
+}
+
+fn decode_scrollback_limits(
+    fields: &mut Fields,
+    prefix: &str,
+    defaults: ScrollbackLimits,
+    diagnostics: &mut Diagnostics,
+) -> ScrollbackLimits {
+    ScrollbackLimits {
+        lines: fields
+            .number(&format!("{}lines", prefix), diagnostics)
+            .unwrap_or(defaults.lines),
+        bytes: fields
+            .size(&format!("{}bytes", prefix), diagnostics)
+            .unwrap_or(defaults.bytes),
+    }
+}
+
+fn decode_processes(
+    list: &Node,
+    shell_program: &[String],
+    scrollback: ScrollbackLimits,
+    diagnostics: &mut Diagnostics,
+) -> Vec<ProcessDefinition> {
+    let Some(items) = list.as_sequence() else {
+        diagnostics.error(
+            &list.location,
+            format!("`process` should be a list, found {}", list.kind()),
+        );
+        return vec![];
+    };
+
+    if items.is_empty() {
+        diagnostics.error(&list.location, "`process` should list at least one process");
+    }
+
+    let vars = decode_vars(items, diagnostics);
+
+    let mut seen: HashMap<&str, &Location> = HashMap::new();
+    let mut processes = vec![];
+    let mut locations = vec![];
+    for item in items {
+        if let Some(name) = item.get("name").and_then(Node::as_str) {
+            match seen.get(name) {
+                Some(first) => diagnostics.error(
+                    &item.location,
+                    format!(
+                        "duplicate process name `{}` (first defined at {})",
+                        name, first
+                    ),
+                ),
+                None => {
+                    seen.insert(name, &item.location);
+                }
+            }
+        }
+
+        if let Some(process) = decode_process(item, &vars, shell_program, scrollback, diagnostics) {
+            processes.push(process);
+            locations.push(&item.location);
+        }
+    }
+
+    check_dependency_cycles(&processes, &locations, diagnostics);
+    processes
+}
+
+fn check_dependency_cycles(
+    processes: &[ProcessDefinition],
+    locations: &[&Location],
+    diagnostics: &mut Diagnostics,
+) {
+    let depends_on: Vec<Vec<usize>> = processes
+        .iter()
+        .map(|process| {
+            process
+                .depends_on
+                .iter()
+                .filter_map(|name| processes.iter().position(|p| &p.name == name))
+                .collect()
+        })
+        .collect();
+
+    if let Err(cycle) = dependencies::start_order(&depends_on) {
+        let names: Vec<&str> = cycle.iter().map(|&i| processes[i].name.as_str()).collect();
+        diagnostics.error(
+            locations[cycle[0]],
+            format!("dependency cycle: {}", names.join(" -> ")),
+        );
+    }
+}
+
+/// Collects every process's `vars:` up front, keyed by process name, so
+/// processes can refer to each other's vars regardless of their order.
+fn decode_vars(
+    items: &[Node],
+    diagnostics: &mut Diagnostics,
+) -> HashMap<String, HashMap<String, String>> {
+    let mut table = HashMap::new();
+    for item in items {
+        let Some(name) = item.get("name").and_then(Node::as_str) else {
+            continue;
+        };
+
+        let mut vars = HashMap::new();
+        let entries = match item.get("vars") {
+            Some(node) if node.as_mapping().is_none() => {
+                diagnostics.error(
+                    &node.location,
+                    format!("`vars` should be a mapping, found {}", node.kind()),
+                );
+                &[][..]
+            }
+            Some(node) => node.as_mapping().unwrap_or_default(),
+            None => &[][..],
+        };
+        for (key, value) in entries {
+            let (Some(key), Some(text)) = (key.as_str(), value.as_str()) else {
+                diagnostics.error(&value.location, "`vars` should map names to strings");
+                continue;
+            };
+            match interpolate::expand_var(text, name, value.location.directory()) {
+                Ok(text) => {
+                    vars.insert(key.to_string(), text);
+                }
+                Err(message) => diagnostics.error(&value.location, message),
+            }
+        }
+        table.entry(name.to_string()).or_insert(vars);
+    }
+    table
+}
+
+fn decode_process(
+    node: &Node,
+    vars: &HashMap<String, HashMap<String, String>>,
+    shell_program: &[String],
+    scrollback: ScrollbackLimits,
+    diagnostics: &mut Diagnostics,
+) -> Option<ProcessDefinition> {
+    let mut fields = Fields::new(node, "process", diagnostics)?;
+
+    let name = fields.required_string("name", diagnostics);
+    // already read by `decode_vars`
+    fields.get("vars");
+
+    let scope = Scope {
+        process: name.as_deref().unwrap_or_default(),
+        vars,
+    };
+    fields.scope = Some(&scope);
+
+    let launch = decode_launch(&mut fields, shell_program, diagnostics);
+    let cwd = fields.required_path("cwd", diagnostics);
+    let auto_start = fields.bool("auto_start", diagnostics);
+    let depends_on = decode_depends_on(&mut fields, name.as_deref(), vars, diagnostics);
+    let readiness = fields
+        .get("ready")
+        .and_then(|node| decode_readiness(node, &scope, shell_program, diagnostics));
+    let restart = match fields.get("restart") {
+        Some(node) => decode_restart(node, diagnostics),
+        None => RestartPolicy::default(),
+    };
+    let stop = decode_stop(&mut fields, shell_program, diagnostics);
+    let environment = decode_environment(&mut fields, diagnostics);
+    let scrollback = match fields.get("scrollback") {
+        Some(node) => decode_process_scrollback(node, scrollback, diagnostics),
+        None => scrollback,
+    };
+    let strip_color = fields.bool("strip_color", diagnostics);
+    let encoding = fields.encoding("encoding", diagnostics);
+    let tty = fields
+        .get("tty")
+        .and_then(|node| decode_tty(node, diagnostics));
+
+    fields.finish(diagnostics);
+
+    Some(ProcessDefinition {
+        name: name?,
+        launch: launch?,
+        cwd: cwd?,
+        auto_start: auto_start.unwrap_or(false),
+        depends_on,
+        readiness,
+        restart,
+        stop,
+        environment,
+        scrollback,
+        strip_color: strip_color.unwrap_or(false),
+        encoding: encoding.unwrap_or(UTF_8),
+        tty,
+    })
+}
+
+/// A process runs either `exe` with `args`, or a `shell` command line.
+fn decode_launch(
+    fields: &mut Fields,
+    shell_program: &[String],
+    diagnostics: &mut Diagnostics,
+) -> Option<Launch> {
+    let location = &fields.node.location;
+    match (fields.has("exe"), fields.has("shell")) {
+        (true, true) => {
+            diagnostics.error(location, "process should have `exe` or `shell`, not both");
+            fields.get("exe");
+            fields.get("shell");
+            // with no telling which of these belongs, neither is unknown
+            fields.get("args");
+            fields.get("shell_program");
+            None
+        }
+        (false, false) => {
+            diagnostics.error(location, "process is missing required key `exe` or `shell`");
+            fields.get("args");
+            fields.get("shell_program");
+            None
+        }
+        (true, false) => {
+            if let Some(node) = fields.get("shell_program") {
+                diagnostics.error(
+                    &node.location,
+                    "`shell_program` only applies to processes that use `shell`",
+                );
+            }
+            let exe = fields.string("exe", diagnostics);
+            let args = fields.string_list("args", diagnostics);
+            Some(Launch::Exe {
+                exe: exe?,
+                args: args.unwrap_or_default(),
+            })
+        }
+        (false, true) => {
+            if let Some(node) = fields.get("args") {
+                diagnostics.error(
+                    &node.location,
+                    "`args` can't be used with `shell`, put them in the command line instead",
+                );
+            }
+            let script = fields.string("shell", diagnostics);
+            let program = fields
+                .shell_program(diagnostics)
+                .unwrap_or_else(|| shell_program.to_vec());
+            Some(Launch::Shell {
+                program,
+                script: script?,
+            })
+        }
+    }
+}
+
+/// `vars` has an entry for every named process, so it doubles as the list
+/// of names a dependency can refer to.
+fn decode_depends_on(
+    fields: &mut Fields,
+    name: Option<&str>,
+    vars: &HashMap<String, HashMap<String, String>>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<String> {
+    let mut depends_on = vec![];
+    for (node, dependency) in fields
+        .string_list_with_nodes("depends_on", diagnostics)
+        .unwrap_or_default()
+    {
+        if Some(dependency.as_str()) == name {
+            diagnostics.error(&node.location, "a process can't depend on itself");
+        } else if !vars.contains_key(&dependency) {
+            diagnostics.error(
+                &node.location,
+                format!("`depends_on` names unknown process `{}`", dependency),
+            );
+        } else if !depends_on.contains(&dependency) {
+            depends_on.push(dependency);
+        }
+    }
+    depends_on
+}
+
+const READINESS_CHECKS: [&str; 4] = ["log", "tcp", "http", "command"];
+
+fn decode_readiness(
+    node: &Node,
+    scope: &Scope,
+    shell_program: &[String],
+    diagnostics: &mut Diagnostics,
+) -> Option<Readiness> {
+    let mut fields = Fields::new(node, "`ready`", diagnostics)?;
+    fields.scope = Some(scope);
+
+    let present: Vec<&str> = READINESS_CHECKS
+        .into_iter()
+        .filter(|key| fields.has(key))
+        .collect();
+    let check = match present[..] {
+        [key] => decode_readiness_check(&mut fields, key, shell_program, diagnostics),
+        [] => {
+            diagnostics.error(
+                &node.location,
+                "`ready` needs one of `log`, `tcp`, `http` or `command`",
+            );
+            None
+        }
+        _ => {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`ready` can only use one check, found `{}`",
+                    present.join("`, `")
+                ),
+            );
+            present.iter().for_each(|key| _ = fields.get(key));
+            None
+        }
+    };
+    let interval = fields
+        .duration("interval", diagnostics)
+        .unwrap_or(Duration::from_secs(1));
+
+    fields.finish(diagnostics);
+    Some(Readiness {
+        check: check?,
+        interval,
+    })
+}
+
+fn decode_readiness_check(
+    fields: &mut Fields,
+    key: &str,
+    shell_program: &[String],
+    diagnostics: &mut Diagnostics,
+) -> Option<ReadinessCheck> {
+    let location = fields.get(key)?.location.clone();
+    let text = fields.string(key, diagnostics)?;
+    let check = match key {
+        "log" => match Regex::new(&text) {
+            Ok(pattern) => Some(ReadinessCheck::Log(pattern)),
+            Err(e) => {
+                diagnostics.error(&location, format!("invalid regex in `log`: {}", e));
+                return None;
+            }
+        },
+        "tcp" => match (text.parse::<u16>(), text.contains(':')) {
+            (Ok(port), _) => Some(ReadinessCheck::Tcp(format!("localhost:{}", port))),
+            (Err(_), true) => Some(ReadinessCheck::Tcp(text)),
+            (Err(_), false) => None,
+        },
+        "http" => HttpTarget::parse(&text).map(ReadinessCheck::Http),
+        _ => Some(ReadinessCheck::Command(Launch::Shell {
+            program: shell_program.to_vec(),
+            script: text,
+        })),
+    };
+
+    if check.is_none() {
+        let expected = match key {
+            "tcp" => "a port number or `host:port`",
+            _ => "an `http://` URL, e.g. `http://localhost:8080/health`",
+        };
+        diagnostics.error(&location, format!("`{}` should be {}", key, expected));
+    }
+    check
+}
+
+/// `restart` is either just the `when` value, or a mapping that also tunes
+/// the backoff.
+fn decode_restart(node: &Node, diagnostics: &mut Diagnostics) -> RestartPolicy {
+    let mut policy = RestartPolicy::default();
+    if let Some(text) = node.as_str() {
+        policy.when = decode_restart_when(node, text, diagnostics).unwrap_or(policy.when);
+        return policy;
+    }
+    let Some(mut fields) = Fields::new(node, "`restart`", diagnostics) else {
+        return policy;
+    };
+
+    if let Some(text) = fields.required_string("when", diagnostics) {
+        let when = fields.get("when").unwrap_or(node);
+        policy.when = decode_restart_when(when, &text, diagnostics).unwrap_or(policy.when);
+    }
+    if let Some(max_retries) = fields.number("max_retries", diagnostics) {
+        policy.max_retries = max_retries;
+    }
+    if let Some(backoff) = fields.duration("backoff", diagnostics) {
+        policy.backoff = backoff;
+    }
+    if let Some(max_backoff) = fields.duration("max_backoff", diagnostics) {
+        policy.max_backoff = max_backoff;
+    }
+    if let Some(codes) = fields.string_list_with_nodes("success_codes", diagnostics) {
+        policy.success_codes = codes
+            .into_iter()
+            .filter_map(|(node, code)| match code.parse() {
+                Ok(code) => Some(code),
+                Err(_) => {
+                    diagnostics.error(
+                        &node.location,
+                        format!("`success_codes` should be exit codes, found `{}`", code),
+                    );
+                    None
+                }
+            })
+            .collect();
+    }
+
+    fields.finish(diagnostics);
+    policy
+}
+
+/// `tty: true` follows the size of the output pane, while a mapping with
+/// `columns` and `rows` fixes it.
+fn decode_tty(node: &Node, diagnostics: &mut Diagnostics) -> Option<TtySize> {
+    if let Some(enabled) = node.as_bool() {
+        return enabled.then_some(TtySize::FollowPane);
+    }
+    if node.as_mapping().is_none() {
+        diagnostics.error(
+            &node.location,
+            format!(
+                "`tty` should be `true`, `false` or a mapping of `columns` and `rows`, found {}",
+                node.kind()
+            ),
+        );
+        return None;
+    }
+    let mut fields = Fields::new(node, "`tty`", diagnostics)?;
+    let defaults = WindowSize::default();
+    let size = WindowSize {
+        columns: fields
+            .number("columns", diagnostics)
+            .unwrap_or(defaults.columns),
+        rows: fields.number("rows", diagnostics).unwrap_or(defaults.rows),
+    };
+    fields.finish(diagnostics);
+    Some(TtySize::Fixed(size))
+}
+
+fn decode_restart_when(
+    node: &Node,
+    text: &str,
+    diagnostics: &mut Diagnostics,
+) -> Option<RestartWhen> {
+    let when = RestartWhen::parse(text);
+    if when.is_none() {
+        diagnostics.error(
+            &node.location,
+            format!(
+                "`restart` should be `never`, `on-failure` or `always`, found `{}`",
+                text
+            ),
+        );
+    }
+    when
+}
+
+fn decode_stop(
+    fields: &mut Fields,
+    shell_program: &[String],
+    diagnostics: &mut Diagnostics,
+) -> StopSettings {
+    let defaults = StopSettings::default();
+
+    let signal = fields.get("stop_signal").and_then(|node| {
+        let text = fields.string("stop_signal", diagnostics)?;
+        let signal = StopSignal::parse(&text);
+        if signal.is_none() {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`stop_signal` should be `SIGTERM`, `SIGINT` or `SIGQUIT`, found `{}`",
+                    text
+                ),
+            );
+        }
+        signal
+    });
+    let command = fields
+        .string("stop_command", diagnostics)
+        .map(|script| Launch::Shell {
+            program: shell_program.to_vec(),
+            script,
+        });
+
+    StopSettings {
+        signal: signal.unwrap_or(defaults.signal),
+        timeout: fields
+            .duration("stop_timeout", diagnostics)
+            .unwrap_or(defaults.timeout),
+        command,
+    }
+}
+
+fn decode_environment(fields: &mut Fields, diagnostics: &mut Diagnostics) -> Environment {
+    let mut vars = vec![];
+    for (key, value) in fields.string_map("env", diagnostics).unwrap_or_default() {
+        let name = key.as_str().unwrap_or_default();
+        match environment::is_valid_name(name) {
+            true => vars.push((name.to_string(), value)),
+            false => diagnostics.error(
+                &key.location,
+                format!("invalid environment variable name `{}`", name),
+            ),
+        }
+    }
+    let files = fields.path_list("env_file", diagnostics);
+    let clear = fields.bool("clear_env", diagnostics).unwrap_or(false);
+    let path_prepend = fields.path_list("path_prepend", diagnostics);
+
+    let inherit = match fields.get("inherit_env") {
+        Some(node) if !clear => {
+            diagnostics.error(
+                &node.location,
+                "`inherit_env` only applies when `clear_env` is true",
+            );
+            vec![]
+        }
+        Some(_) => fields
+            .string_list("inherit_env", diagnostics)
+            .unwrap_or_default(),
+        None => vec![],
+    };
+
+    Environment {
+        vars,
+        files: files.unwrap_or_default(),
+        clear,
+        inherit,
+        path_prepend: path_prepend.unwrap_or_default(),
+    }
+}
+
+/// Reads keys out of a mapping and remembers which ones were used, so that
+/// anything left over can be reported as an unknown key.
+struct Fields<'a> {
+    node: &'a Node,
+    context: &'static str,
+    entries: &'a [(Node, Node)],
+    used: Vec<bool>,
+    /// When set, `${...}` references in strings are expanded as they're read.
+    scope: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(node: &'a Node, context: &'static str, diagnostics: &mut Diagnostics) -> Option<Self> {
+        match node.as_mapping() {
+            Some(entries) => Some(Self {
+                node,
+                context,
+                entries,
+                used: vec![false; entries.len()],
+                scope: None,
+            }),
+            None => {
+                diagnostics.error(
+                    &node.location,
+                    format!("{} should be a mapping, found {}", context, node.kind()),
+                );
+                None
+            }
+        }
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.entries.iter().any(|(k, _)| k.as_str() == Some(key))
+    }
+
+    fn get(&mut self, key: &str) -> Option<&'a Node> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(k, _)| k.as_str() == Some(key))?;
+        self.used[index] = true;
+        Some(&self.entries[index].1)
+    }
+
+    /// The text of a scalar, with references expanded if there's a scope.
+    fn text(&self, node: &Node, diagnostics: &mut Diagnostics) -> Option<String> {
+        let text = node.as_str()?;
+        let Some(scope) = self.scope else {
+            return Some(text.to_string());
+        };
+        match scope.expand(text, node.location.directory()) {
+            Ok(text) => Some(text),
+            Err(message) => {
+                diagnostics.error(&node.location, message);
+                None
+            }
+        }
+    }
+
+    fn string(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<String> {
+        let node = self.get(key)?;
+        if node.as_str().is_none() {
+            diagnostics.error(
+                &node.location,
+                format!("`{}` should be a string, found {}", key, node.kind()),
+            );
+            return None;
+        }
+        self.text(node, diagnostics)
+    }
+
+    fn required_string(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<String> {
+        if !self.has(key) {
+            diagnostics.error(
+                &self.node.location,
+                format!("{} is missing required key `{}`", self.context, key),
+            );
+            return None;
+        }
+        self.string(key, diagnostics)
+    }
+
+    fn required_path(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<PathBuf> {
+        let path = self.required_string(key, diagnostics)?;
+        let directory = self.get(key)?.location.directory();
+        Some(interpolate::resolve_path(&path, directory))
+    }
+
+    fn string_list(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<Vec<String>> {
+        let values = self.string_list_with_nodes(key, diagnostics)?;
+        Some(values.into_iter().map(|(_, value)| value).collect())
+    }
+
+    /// Like `string_list`, but each value comes with the node it was read
+    /// from so callers can point at the right entry.
+    fn string_list_with_nodes(
+        &mut self,
+        key: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Vec<(&'a Node, String)>> {
+        let node = self.get(key)?;
+        let Some(items) = node.as_sequence() else {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`{}` should be a list of strings, found {}",
+                    key,
+                    node.kind()
+                ),
+            );
+            return None;
+        };
+
+        let mut values = vec![];
+        for item in items {
+            match item.as_str() {
+                Some(_) => values.extend(self.text(item, diagnostics).map(|value| (item, value))),
+                None => diagnostics.error(
+                    &item.location,
+                    format!("`{}` entries should be strings, found {}", key, item.kind()),
+                ),
+            }
+        }
+        Some(values)
+    }
+
+    /// `shell_program`: the shell plus the flags that make it run a command.
+    fn shell_program(&mut self, diagnostics: &mut Diagnostics) -> Option<Vec<String>> {
+        let program = self.string_list("shell_program", diagnostics)?;
+        if program.is_empty() {
+            let location = &self.get("shell_program")?.location;
+            diagnostics.error(location, "`shell_program` should at least name the shell");
+            return None;
+        }
+        Some(program)
+    }
+
+    /// A duration such as `500ms`, `2s`, `1.5m` or `1h`. A bare number is
+    /// in seconds.
+    fn duration(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<Duration> {
+        let node = self.get(key)?;
+        let text = self.string(key, diagnostics)?;
+        let duration = parse_duration(&text);
+        if duration.is_none() {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`{}` should be a duration like `500ms`, `2s` or `1m`, found `{}`",
+                    key, text
+                ),
+            );
+        }
+        duration
+    }
+
+    /// A size such as `512KB` or `16MB`. A bare number is in bytes.
+    fn size(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<usize> {
+        let node = self.get(key)?;
+        let text = self.string(key, diagnostics)?;
+        let size = scrollback::parse_size(&text);
+        if size.is_none() {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`{}` should be a size like `512KB` or `16MB`, found `{}`",
+                    key, text
+                ),
+            );
+        }
+        size
+    }
+
+    /// A character encoding by any of its WHATWG labels, like `utf-8`,
+    /// `latin1` or `shift_jis`.
+    fn encoding(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<&'static Encoding> {
+        let node = self.get(key)?;
+        let text = self.string(key, diagnostics)?;
+        let encoding = Encoding::for_label(text.trim().as_bytes());
+        if encoding.is_none() {
+            diagnostics.error(
+                &node.location,
+                format!(
+                    "`{}` should be an encoding like `utf-8`, found `{}`",
+                    key, text
+                ),
+            );
+        }
+        encoding
+    }
+
+    /// Like `string_list`, but relative paths are resolved against the
+    /// directory of the file they were written in.
+    fn path_list(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<Vec<PathBuf>> {
+        let node = self.get(key)?;
+        let directory = node.location.directory();
+        let paths = self.string_list(key, diagnostics)?;
+        Some(
+            paths
+                .iter()
+                .map(|path| interpolate::resolve_path(path, directory))
+                .collect(),
+        )
+    }
+
+    /// A mapping of scalar keys to scalar values, in file order. The key nodes
+    /// are returned so callers can validate them and point at the right line.
+    fn string_map(
+        &mut self,
+        key: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Vec<(&'a Node, String)>> {
+        let node = self.get(key)?;
+        let Some(entries) = node.as_mapping() else {
+            diagnostics.error(
+                &node.location,
+                format!("`{}` should be a mapping, found {}", key, node.kind()),
+            );
+            return None;
+        };
+
+        let mut values = vec![];
+        for (name, value) in entries {
+            match (name.as_str(), value.as_str()) {
+                (Some(_), Some(_)) => {
+                    if let Some(value) = self.text(value, diagnostics) {
+                        values.push((name, value));
+                    }
+                }
+                (None, _) => diagnostics.error(
+                    &name.location,
+                    format!("`{}` keys should be strings, found {}", key, name.kind()),
+                ),
+                (_, None) => diagnostics.error(
+                    &value.location,
+                    format!("`{}` values should be strings, found {}", key, value.kind()),
+                ),
+            }
+        }
+        Some(values)
+    }
+
+    fn number<T: std::str::FromStr>(
+        &mut self,
+        key: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<T> {
+        let node = self.get(key)?;
+        let text = self.string(key, diagnostics)?;
+        let number = text.parse().ok();
+        if number.is_none() {
+            diagnostics.error(
+                &node.location,
+                format!("`{}` should be a whole number, found `{}`", key, text),
+            );
+        }
+        number
+    }
+
+    fn bool(&mut self, key: &str, diagnostics: &mut Diagnostics) -> Option<bool> {
+        let node = self.get(key)?;
+        match node.as_bool() {
+            Some(value) => Some(value),
+            None => {
+                diagnostics.error(
+                    &node.location,
+                    format!("`{}` should be `true` or `false`", key),
+                );
+                None
+            }
+        }
+    }
+
+    /// Reports every key that wasn't read.
+    fn finish(self, diagnostics: &mut Diagnostics) {
+        for ((key, _), used) in self.entries.iter().zip(self.used) {
+            if !used {
+                diagnostics.error(
+                    &key.location,
+                    format!(
+                        "unknown key `{}` in {}",
+                        key.as_str().unwrap_or("?"),
+                        self.context
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// A number with its unit, `ms`, `s`, `m` or `h`. A bare number could mean
+/// seconds or milliseconds, so it isn't taken as either.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Writes `files` into a new directory and loads the first one.
+    fn load_files(files: &[(&str, &str)]) -> (TempDir, Result<Configuration, MultiHostError>) {
+        let directory = tempfile::tempdir().unwrap();
+        for (name, source) in files {
+            fs::write(directory.path().join(name), source).unwrap();
+        }
+        let result = load(&directory.path().join(files[0].0));
+        (directory, result)
+    }
+
+    /// The problems reported, with the directory taken out of their paths.
+    fn problems(files: &[(&str, &str)]) -> String {
+        let (directory, result) = load_files(files);
+        let Err(MultiHostError::Config(diagnostics)) = result else {
+            panic!("expected the config to be rejected");
+        };
+        let prefix = format!("{}/", directory.path().display());
+        diagnostics.to_string().replace(&prefix, "")
+    }
+
+    #[test]
+    fn loads_a_valid_config() {
+        let (_directory, result) = load_files(&[(
+            "multi-host.yaml",
+            "process:\n- name: web\n  exe: npm\n  cwd: .\n  args: [run, dev]\n  auto_start: true\n- name: db\n  exe: postgres\n  cwd: db\n",
+        )]);
+        let config = result.unwrap();
+
+        let names: Vec<&str> = config.processes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["web", "db"]);
+        assert_eq!(
+            config.processes[0].launch,
+            Launch::Exe {
+                exe: "npm".to_string(),
+                args: vec!["run".to_string(), "dev".to_string()],
+            }
+        );
+        assert!(config.processes[0].auto_start);
+        assert!(!config.processes[1].auto_start);
+    }
+
+    #[test]
+    fn loads_with_includes_and_local_override() {
+        let (directory, result) = load_files(&[
+            (
+                "multi-host.yaml",
+                "include: [team.yaml]\nprocess:\n- name: web\n  exe: npm\n  cwd: .\n",
+            ),
+            (
+                "team.yaml",
+                "process:\n- name: api\n  exe: ./api\n  cwd: api\n",
+            ),
+            (
+                "multi-host.local.yaml",
+                "process:\n- name: web\n  auto_start: true\n",
+            ),
+        ]);
+        let config = result.unwrap();
+
+        let names: Vec<&str> = config.processes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["api", "web"]);
+        assert!(config.processes[1].auto_start);
+        assert_eq!(config.processes[0].cwd, directory.path().join("api"));
+        assert_eq!(
+            config.sources,
+            ["multi-host.yaml", "team.yaml", "multi-host.local.yaml"]
+                .map(|name| directory.path().join(name))
+        );
+    }
+
+    #[test]
+    fn problems_in_other_files_point_there() {
+        let files = [
+            (
+                "multi-host.yaml",
+                "include: [team.yaml]\nprocess:\n- name: web\n  exe: npm\n  cwd: .\n",
+            ),
+            (
+                "team.yaml",
+                "reload:\n  watch: true\nprocess:\n- name: api\n  exe: [x]\n  cwd: .\n",
+            ),
+            (
+                "multi-host.local.yaml",
+                "process:\n- name: web\n  auto_start: 3\n",
+            ),
+        ];
+        assert_eq!(
+            problems(&files),
+            "\
+team.yaml:1:1: `reload` can only be set in the main config file
+team.yaml:5:8: `exe` should be a string, found a list
+multi-host.local.yaml:3:15: `auto_start` should be `true` or `false`"
+        );
+    }
+
+    #[test]
+    fn unreadable_local_override_is_reported() {
+        let config = "process:\n- name: web\n  exe: npm\n  cwd: .\n";
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("multi-host.yaml"), config).unwrap();
+        let local_path = directory.path().join("multi-host.local.yaml");
+        fs::write(&local_path, b"process: \xff\n").unwrap();
+
+        let Err(MultiHostError::Config(diagnostics)) =
+            load(&directory.path().join("multi-host.yaml"))
+        else {
+            panic!("expected the config to be rejected");
+        };
+        assert_eq!(
+            diagnostics.to_string(),
+            format!(
+                "{0}:1:1: could not read local override `{0}`: stream did not contain valid UTF-8",
+                local_path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported_where_it_is() {
+        let config = "\
+process:
+- name: web
+  exe: npm
+  cwd: .
+  auto_start: maybe
+  colour: red
+  depends_on: [db, nope, web]
+- name: db
+  exe: [postgres]
+- name: web
+  exe: npm
+  cwd: .
+  args: [1, [x]]
+- name: api
+  shell: ./api
+  exe: api
+  cwd: .
+  stop_timeout: soon
+";
+        assert_eq!(
+            problems(&[("multi-host.yaml", config)]),
+            "\
+multi-host.yaml:5:15: `auto_start` should be `true` or `false`
+multi-host.yaml:7:20: `depends_on` names unknown process `nope`
+multi-host.yaml:7:26: a process can't depend on itself
+multi-host.yaml:6:3: unknown key `colour` in process
+multi-host.yaml:9:8: `exe` should be a string, found a list
+multi-host.yaml:8:7: process is missing required key `cwd`
+multi-host.yaml:10:7: duplicate process name `web` (first defined at multi-host.yaml:2:7)
+multi-host.yaml:13:13: `args` entries should be strings, found a list
+multi-host.yaml:14:7: process should have `exe` or `shell`, not both
+multi-host.yaml:18:17: `stop_timeout` should be a duration like `500ms`, `2s` or `1m`, found `soon`"
+        );
+    }
+
+    #[test]
+    fn readiness_targets() {
+        let config = "\
+process:
+- name: db
+  exe: postgres
+  cwd: .
+  ready: { tcp: 5432 }
+- name: cache
+  exe: redis-server
+  cwd: .
+  ready: { tcp: \"cache.test:6379\" }
+- name: api
+  exe: ./api
+  cwd: .
+  ready: { http: \"http://localhost:8080/health\" }
+";
+        let (_directory, result) = load_files(&[("multi-host.yaml", config)]);
+        let checks: Vec<ReadinessCheck> = result
+            .unwrap()
+            .processes
+            .iter()
+            .map(|process| process.readiness.clone().unwrap().check)
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                ReadinessCheck::Tcp("localhost:5432".to_string()),
+                ReadinessCheck::Tcp("cache.test:6379".to_string()),
+                ReadinessCheck::Http(HttpTarget::parse("http://localhost:8080/health").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_readiness_targets_are_reported() {
+        let config = "\
+process:
+- name: db
+  exe: postgres
+  cwd: .
+  ready: { tcp: postgres }
+- name: api
+  exe: ./api
+  cwd: .
+  ready: { http: \"https://localhost/health\" }
+";
+        assert_eq!(
+            problems(&[("multi-host.yaml", config)]),
+            "\
+multi-host.yaml:5:17: `tcp` should be a port number or `host:port`
+multi-host.yaml:9:18: `http` should be an `http://` URL, e.g. `http://localhost:8080/health`"
+        );
+    }
+
+    #[test]
+    fn dependency_cycles_are_reported() {
+        let config = "\
+process:
+- name: web
+  exe: npm
+  cwd: .
+  depends_on: [api]
+- name: api
+  exe: ./api
+  cwd: .
+  depends_on: [web]
+";
+        assert_eq!(
+            problems(&[("multi-host.yaml", config)]),
+            "multi-host.yaml:2:7: dependency cycle: web -> api -> web"
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration(" 1.5 s "), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("10 seconds"), None);
+        assert_eq!(parse_duration("1e400s"), None);
+    }
+
+    #[test]
+    fn references_are_expanded_where_they_are_written() {
+        let config = "process:\n- name: web\n  exe: npm\n  cwd: .\n  args: [\"${MULTI_HOST_TEST_SURELY_UNSET}\"]\n";
+        assert_eq!(
+            problems(&[("multi-host.yaml", config)]),
+            "multi-host.yaml:5:10: environment variable `MULTI_HOST_TEST_SURELY_UNSET` is not set"
+        );
+    }
+
+    #[test]
+    fn syntax_errors_and_empty_files() {
+        assert_eq!(
+            problems(&[(
+                "multi-host.yaml",
+                "process:\n- name: web\n  exe: npm\n   bad: 1\n"
+            )]),
+            "multi-host.yaml:4:7: mapping values are not allowed in this context"
+        );
+        assert_eq!(
+            problems(&[("multi-host.yaml", "")]),
+            "multi-host.yaml:1:1: config file is empty"
+        );
+        assert_eq!(
+            problems(&[("multi-host.yaml", "processes: []\n")]),
+            "multi-host.yaml:1:10: missing required key `process`\n\
+             multi-host.yaml:1:1: unknown key `processes` in config file"
+        );
+    }
+}
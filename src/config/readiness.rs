@@ -0,0 +1,248 @@
+use regex::Regex;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::environment::Environment;
+use super::launch::Launch;
+
+/// The `ready:` section: how to tell that a started process is actually
+/// able to serve its dependents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Readiness {
+    pub check: ReadinessCheck,
+    /// How long to wait between attempts of a polled check.
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum ReadinessCheck {
+    /// A line of output matches.
+    Log(Regex),
+    /// Something accepts connections on `host:port`.
+    Tcp(String),
+    /// A GET of this `http://` URL answers with a 2xx status.
+    Http(HttpTarget),
+    /// A command run in the process's directory and environment exits 0.
+    Command(Launch),
+}
+
+impl PartialEq for ReadinessCheck {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ReadinessCheck::Log(a), ReadinessCheck::Log(b)) => a.as_str() == b.as_str(),
+            (ReadinessCheck::Tcp(a), ReadinessCheck::Tcp(b)) => a == b,
+            (ReadinessCheck::Http(a), ReadinessCheck::Http(b)) => a == b,
+            (ReadinessCheck::Command(a), ReadinessCheck::Command(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpTarget {
+    /// `host:port`, with the port defaulting to 80.
+    pub address: String,
+    pub host: String,
+    pub path: String,
+}
+
+impl HttpTarget {
+    /// Only plain `http://` is supported; this is for local health checks.
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (host, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+            _ => format!("{}:80", host),
+        };
+        Some(Self {
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+impl ReadinessCheck {
+    /// Whether the check is answered by watching output rather than polling.
+    pub fn is_log(&self) -> bool {
+        matches!(self, ReadinessCheck::Log(_))
+    }
+
+    pub fn matches_line(&self, line: &str) -> bool {
+        match self {
+            ReadinessCheck::Log(pattern) => pattern.is_match(line),
+            _ => false,
+        }
+    }
+
+    /// Makes one attempt at a polled check.
+    pub fn poll(&self, cwd: &Path, environment: &Environment) -> bool {
+        match self {
+            ReadinessCheck::Log(_) => false,
+            ReadinessCheck::Tcp(address) => connect(address).is_some(),
+            ReadinessCheck::Http(target) => http_ok(target),
+            ReadinessCheck::Command(launch) => {
+                let mut cmd = Command::new(launch.program());
+                cmd.args(launch.args())
+                    .current_dir(cwd)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                environment.apply(&mut cmd).is_ok() && succeeds(cmd)
+            }
+        }
+    }
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// far more than any status line needs, so a server that never ends it
+// can't be read forever
+const MAX_STATUS_LINE: usize = 1024;
+// a check command that hangs counts as a failed attempt rather than holding
+// up the ones after it
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const COMMAND_POLL: Duration = Duration::from_millis(50);
+
+/// Whether `cmd` exits 0 within `COMMAND_TIMEOUT`. It's killed if not.
+fn succeeds(mut cmd: Command) -> bool {
+    let Ok(mut child) = cmd.spawn() else {
+        return false;
+    };
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return status.success(),
+            Ok(None) if Instant::now() < deadline => thread::sleep(COMMAND_POLL),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return false;
+            }
+        }
+    }
+}
+
+fn connect(address: &str) -> Option<TcpStream> {
+    address
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|address| TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).ok())
+}
+
+fn http_ok(target: &HttpTarget) -> bool {
+    let Some(mut stream) = connect(&target.address) else {
+        return false;
+    };
+    let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(CONNECT_TIMEOUT));
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target.path, target.host
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+
+    status_code(stream).is_some_and(|code| (200..300).contains(&code))
+}
+
+/// The code in the status line at the start of `response`, e.g.
+/// `HTTP/1.1 204 No Content`. The line is read up to its end, however many
+/// pieces it arrives in.
+fn status_code(mut response: impl Read) -> Option<u16> {
+    let mut status_line = vec![];
+    let mut buffer = [0; 64];
+    while !status_line.contains(&b'\n') && status_line.len() < MAX_STATUS_LINE {
+        match response.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => status_line.extend_from_slice(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return None,
+        }
+    }
+    String::from_utf8_lossy(&status_line)
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out its pieces one read at a time, like a server writing its
+    /// response in several goes.
+    struct Pieces(Vec<&'static [u8]>);
+
+    impl Read for Pieces {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.is_empty() {
+                true => Ok(0),
+                false => {
+                    let piece = self.0.remove(0);
+                    buf[..piece.len()].copy_from_slice(piece);
+                    Ok(piece.len())
+                }
+            }
+        }
+    }
+
+    fn target(address: &str, host: &str, path: &str) -> Option<HttpTarget> {
+        Some(HttpTarget {
+            address: address.to_string(),
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    #[test]
+    fn http_urls() {
+        assert_eq!(
+            HttpTarget::parse("http://localhost:8080/health?full=1"),
+            target("localhost:8080", "localhost:8080", "/health?full=1")
+        );
+        assert_eq!(
+            HttpTarget::parse("http://example.test"),
+            target("example.test:80", "example.test", "/")
+        );
+        assert_eq!(
+            HttpTarget::parse("http://[::1]:3000/"),
+            target("[::1]:3000", "[::1]:3000", "/")
+        );
+        assert_eq!(HttpTarget::parse("https://localhost/"), None);
+        assert_eq!(HttpTarget::parse("localhost:8080"), None);
+        assert_eq!(HttpTarget::parse("http:///health"), None);
+    }
+
+    #[test]
+    fn status_line_in_one_piece() {
+        let response = Pieces(vec![b"HTTP/1.1 204 No Content\r\nServer: x\r\n\r\n"]);
+        assert_eq!(status_code(response), Some(204));
+    }
+
+    #[test]
+    fn status_line_in_several_pieces() {
+        let response = Pieces(vec![b"HTTP/1.1", b" 2", b"00 OK\r", b"\n"]);
+        assert_eq!(status_code(response), Some(200));
+        let response = Pieces(vec![b"HTTP/1.0 ", b"503"]);
+        assert_eq!(status_code(response), Some(503));
+    }
+
+    #[test]
+    fn no_status_line() {
+        assert_eq!(status_code(Pieces(vec![])), None);
+        assert_eq!(status_code(Pieces(vec![b"SSH-2.0-OpenSSH\r\n"])), None);
+    }
+}
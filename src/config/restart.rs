@@ -0,0 +1,116 @@
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// The `restart:` setting: what to do when a process exits on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestartPolicy {
+    pub when: RestartWhen,
+    /// Restarts in a row before a failed exit is given up on and called a
+    /// crash loop. A clean exit starts the count again.
+    pub max_retries: u32,
+    /// Delay before the first restart, doubled for each one after that.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Exit codes that count as a clean exit for `on-failure`.
+    pub success_codes: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartWhen {
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartWhen {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "never" => Some(RestartWhen::Never),
+            "on-failure" => Some(RestartWhen::OnFailure),
+            "always" => Some(RestartWhen::Always),
+            _ => None,
+        }
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            when: RestartWhen::Never,
+            max_retries: 5,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            success_codes: vec![0],
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Being killed by a signal is never a success.
+    pub fn is_success(&self, status: ExitStatus) -> bool {
+        status
+            .code()
+            .is_some_and(|code| self.success_codes.contains(&code))
+    }
+
+    pub fn should_restart(&self, status: ExitStatus) -> bool {
+        match self.when {
+            RestartWhen::Never => false,
+            RestartWhen::OnFailure => !self.is_success(status),
+            RestartWhen::Always => true,
+        }
+    }
+
+    /// The delay before restart number `attempt`, counting from 1.
+    pub fn backoff_for(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff: u64, max_backoff: u64) -> RestartPolicy {
+        RestartPolicy {
+            backoff: Duration::from_secs(backoff),
+            max_backoff: Duration::from_secs(max_backoff),
+            ..RestartPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_from_the_first_restart() {
+        let policy = policy(1, 30);
+        let delays: Vec<u64> = (1..=5)
+            .map(|attempt| policy.backoff_for(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn backoff_stops_at_the_cap() {
+        let policy = policy(1, 30);
+        assert_eq!(policy.backoff_for(6), Duration::from_secs(30));
+        assert_eq!(policy.backoff_for(40), Duration::from_secs(30));
+        assert_eq!(policy.backoff_for(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn backoff_never_goes_over_a_low_cap() {
+        let policy = policy(5, 3);
+        assert_eq!(policy.backoff_for(1), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn restart_when() {
+        assert_eq!(RestartWhen::parse("never"), Some(RestartWhen::Never));
+        assert_eq!(
+            RestartWhen::parse("on-failure"),
+            Some(RestartWhen::OnFailure)
+        );
+        assert_eq!(RestartWhen::parse("always"), Some(RestartWhen::Always));
+        assert_eq!(RestartWhen::parse("sometimes"), None);
+    }
+}
@@ -0,0 +1,50 @@
+const MB: usize = 1024 * 1024;
+
+/// How much output is kept before the oldest lines are dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollbackLimits {
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// The `scrollback:` section. Processes can override `process` with their own
+/// `scrollback:`, while `total` covers all of them together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollbackSettings {
+    pub process: ScrollbackLimits,
+    pub total: ScrollbackLimits,
+}
+
+impl Default for ScrollbackSettings {
+    fn default() -> Self {
+        Self {
+            process: ScrollbackLimits {
+                lines: 50_000,
+                bytes: 16 * MB,
+            },
+            total: ScrollbackLimits {
+                lines: 500_000,
+                bytes: 256 * MB,
+            },
+        }
+    }
+}
+
+/// A size such as `512KB`, `16MB` or `1GB`, in multiples of 1024. A bare
+/// number is in bytes.
+pub fn parse_size(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: usize = number.parse().ok()?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => MB,
+        "GB" => 1024 * MB,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use super::launch::Launch;
+
+/// How a hosted process is asked to stop before it gets killed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopSettings {
+    pub signal: StopSignal,
+    /// How long the process gets to exit before it is sent SIGKILL.
+    pub timeout: Duration,
+    /// Run instead of sending `signal`, e.g. a server's own shutdown script.
+    pub command: Option<Launch>,
+}
+
+impl Default for StopSettings {
+    fn default() -> Self {
+        Self {
+            signal: StopSignal::Term,
+            timeout: Duration::from_secs(10),
+            command: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopSignal {
+    Term,
+    Int,
+    Quit,
+}
+
+impl StopSignal {
+    /// Accepts `SIGTERM` or just `TERM`, in any case.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.to_ascii_uppercase();
+        match text.strip_prefix("SIG").unwrap_or(&text) {
+            "TERM" => Some(StopSignal::Term),
+            "INT" => Some(StopSignal::Int),
+            "QUIT" => Some(StopSignal::Quit),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StopSignal::Term => "SIGTERM",
+            StopSignal::Int => "SIGINT",
+            StopSignal::Quit => "SIGQUIT",
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn number(self) -> libc::c_int {
+        match self {
+            StopSignal::Term => libc::SIGTERM,
+            StopSignal::Int => libc::SIGINT,
+            StopSignal::Quit => libc::SIGQUIT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(StopSignal::parse("SIGTERM"), Some(StopSignal::Term));
+        assert_eq!(StopSignal::parse("TERM"), Some(StopSignal::Term));
+        assert_eq!(StopSignal::parse("sigint"), Some(StopSignal::Int));
+        assert_eq!(StopSignal::parse("Quit"), Some(StopSignal::Quit));
+        for signal in [StopSignal::Term, StopSignal::Int, StopSignal::Quit] {
+            assert_eq!(StopSignal::parse(signal.name()), Some(signal));
+        }
+    }
+
+    #[test]
+    fn unknown_signals() {
+        assert_eq!(StopSignal::parse("SIGKILL"), None);
+        assert_eq!(StopSignal::parse("SIG"), None);
+        assert_eq!(StopSignal::parse("15"), None);
+        assert_eq!(StopSignal::parse(""), None);
+    }
+}
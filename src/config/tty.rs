@@ -0,0 +1,23 @@
+/// The size of a terminal, in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub columns: u16,
+    pub rows: u16,
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self {
+            columns: 80,
+            rows: 24,
+        }
+    }
+}
+
+/// How big the pseudo-terminal of a `tty:` process is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtySize {
+    /// The size of the output pane, following it as it's resized.
+    FollowPane,
+    Fixed(WindowSize),
+}
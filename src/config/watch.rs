@@ -0,0 +1,35 @@
+use async_std::task;
+use iced::futures::{SinkExt, Stream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::Message;
+
+/// Polls the modification times of the config's source files and emits
+/// `Message::ConfigFileChanged` whenever one of them moves.
+pub fn watch(paths: Vec<PathBuf>) -> impl Stream<Item = Message> {
+    iced::stream::channel(1, |mut output| async move {
+        let mut last_seen: Vec<_> = paths.iter().map(|path| modified(path)).collect();
+        loop {
+            task::sleep(Duration::from_secs(1)).await;
+
+            let current: Vec<_> = paths.iter().map(|path| modified(path)).collect();
+            // A missing file is usually an editor swapping it out mid-save,
+            // so wait for it to come back rather than reporting it. This also
+            // means deleting the local override only applies on the next edit.
+            let changed = current
+                .iter()
+                .zip(&last_seen)
+                .any(|(now, before)| now.is_some() && now != before);
+            last_seen = current;
+            if changed {
+                let _ = output.send(Message::ConfigFileChanged).await;
+            }
+        }
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
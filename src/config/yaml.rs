@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+use super::diagnostic::{Diagnostics, Location};
+
+/// A YAML node that remembers where it came from. `yaml_rust2::Yaml` throws
+/// positions away, so we build our own tree straight from the parser events.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub value: Value,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// `plain` is false for quoted and block scalars, which are always strings.
+    Scalar {
+        text: String,
+        plain: bool,
+    },
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+impl Node {
+    pub fn is_null(&self) -> bool {
+        match &self.value {
+            Value::Scalar { text, plain: true } => matches!(text.as_str(), "" | "~" | "null"),
+            _ => false,
+        }
+    }
+
+    /// Any non-null scalar. Plain numbers and booleans are accepted as their text.
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar { text, .. } if !self.is_null() => Some(text.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match &self.value {
+            Value::Scalar { text, plain: true } => match text.as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn as_sequence(&self) -> Option<&[Node]> {
+        match &self.value {
+            Value::Sequence(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_mapping(&self) -> Option<&[(Node, Node)]> {
+        match &self.value {
+            Value::Mapping(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` in a mapping node.
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        self.as_mapping()?
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Node> {
+        match &mut self.value {
+            Value::Mapping(entries) => entries
+                .iter_mut()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Appends an entry to a mapping node. Does nothing for other nodes.
+    pub fn insert(&mut self, key: Node, value: Node) {
+        if let Value::Mapping(entries) = &mut self.value {
+            entries.push((key, value));
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Node> {
+        let Value::Mapping(entries) = &mut self.value else {
+            return None;
+        };
+        let index = entries.iter().position(|(k, _)| k.as_str() == Some(key))?;
+        Some(entries.remove(index).1)
+    }
+
+    /// Returns the value for `key`, inserting `default()` first if the
+    /// mapping doesn't have one. The new key shares the default's location.
+    pub fn entry(&mut self, key: &str, default: impl FnOnce() -> Node) -> &mut Node {
+        if self.get(key).is_none() {
+            let value = default();
+            let key = Node {
+                value: Value::Scalar {
+                    text: key.to_string(),
+                    plain: true,
+                },
+                location: value.location.clone(),
+            };
+            self.insert(key, value);
+        }
+        self.get_mut(key).expect("entry should exist after insert")
+    }
+
+    /// Describes the node for "expected X, found Y" messages.
+    pub fn kind(&self) -> &'static str {
+        match &self.value {
+            _ if self.is_null() => "null",
+            Value::Scalar { .. } => "a scalar",
+            Value::Sequence(_) => "a list",
+            Value::Mapping(_) => "a mapping",
+        }
+    }
+}
+
+/// Parses the first document in `source`. Returns `None` for an empty file.
+pub fn parse(file: Arc<Path>, source: &str) -> Result<Option<Node>, Diagnostics> {
+    let mut builder = TreeBuilder {
+        file,
+        stack: vec![],
+        anchors: HashMap::new(),
+        documents: vec![],
+        diagnostics: Diagnostics::default(),
+    };
+
+    let mut parser = Parser::new_from_str(source);
+    if let Err(e) = parser.load(&mut builder, true) {
+        let location = builder.location(*e.marker());
+        builder.diagnostics.error(&location, e.info());
+    }
+
+    match builder.diagnostics.is_empty() {
+        true => Ok(builder.documents.into_iter().next()),
+        false => Err(builder.diagnostics),
+    }
+}
+
+enum Partial {
+    Sequence(Location, usize, Vec<Node>),
+    Mapping(Location, usize, Vec<(Node, Node)>, Option<Node>),
+}
+
+struct TreeBuilder {
+    file: Arc<Path>,
+    stack: Vec<Partial>,
+    anchors: HashMap<usize, Node>,
+    documents: Vec<Node>,
+    diagnostics: Diagnostics,
+}
+
+impl TreeBuilder {
+    fn location(&self, mark: Marker) -> Location {
+        Location {
+            file: Arc::clone(&self.file),
+            line: mark.line(),
+            // yaml-rust2 columns start at zero
+            column: mark.col() + 1,
+        }
+    }
+
+    fn finish_node(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+
+        match self.stack.last_mut() {
+            None => self.documents.push(node),
+            Some(Partial::Sequence(_, _, items)) => items.push(node),
+            Some(Partial::Mapping(_, _, entries, key)) => match key.take() {
+                None => *key = Some(node),
+                Some(key) => {
+                    let duplicate = entries
+                        .iter()
+                        .find(|(existing, _)| {
+                            key.as_str().is_some() && existing.as_str() == key.as_str()
+                        })
+                        .map(|(existing, _)| existing.location.line);
+                    match duplicate {
+                        Some(line) => self.diagnostics.error(
+                            &key.location,
+                            format!(
+                                "duplicate key `{}` (first defined on line {})",
+                                key.as_str().unwrap_or_default(),
+                                line
+                            ),
+                        ),
+                        None => entries.push((key, node)),
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(text, style, anchor, _) => {
+                let node = Node {
+                    value: Value::Scalar {
+                        text,
+                        plain: style == TScalarStyle::Plain,
+                    },
+                    location: self.location(mark),
+                };
+                self.finish_node(node, anchor);
+            }
+            Event::SequenceStart(anchor, _) => {
+                let location = self.location(mark);
+                self.stack.push(Partial::Sequence(location, anchor, vec![]));
+            }
+            Event::MappingStart(anchor, _) => {
+                let location = self.location(mark);
+                self.stack
+                    .push(Partial::Mapping(location, anchor, vec![], None));
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                let (node, anchor) = match self.stack.pop() {
+                    Some(Partial::Sequence(location, anchor, items)) => (
+                        Node {
+                            value: Value::Sequence(items),
+                            location,
+                        },
+                        anchor,
+                    ),
+                    Some(Partial::Mapping(location, anchor, entries, _)) => (
+                        Node {
+                            value: Value::Mapping(entries),
+                            location,
+                        },
+                        anchor,
+                    ),
+                    None => return,
+                };
+                self.finish_node(node, anchor);
+            }
+            Event::Alias(anchor) => match self.anchors.get(&anchor) {
+                Some(node) => self.finish_node(node.clone(), 0),
+                None => {
+                    let location = self.location(mark);
+                    self.diagnostics
+                        .error(&location, "alias to an unknown anchor");
+                }
+            },
+            Event::Nothing
+            | Event::StreamStart
+            | Event::StreamEnd
+            | Event::DocumentStart
+            | Event::DocumentEnd => (),
+        }
+    }
+}
@@ -1,101 +1,432 @@
+use async_std::channel;
+use async_std::future;
+use async_std::io::Read;
+use async_std::prelude::FutureExt;
+use async_std::process::Child;
+use async_std::task;
+use encoding_rs::Encoding;
 use iced::Length::Fill;
 use iced::futures::channel::mpsc::{self, Sender};
-use iced::futures::executor::block_on;
 use iced::futures::{SinkExt, Stream, StreamExt};
-use iced::widget::{button, row};
+use iced::widget::{button, row, text};
+use std::fmt;
 use std::fmt::Write;
-use std::io::{BufRead, BufReader, Lines};
 use std::path::PathBuf;
-use std::process::{Child, ChildStderr, ChildStdout, Command, Stdio};
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::Duration;
-use std::{env, fmt, thread};
+use std::pin::Pin;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::ProcessDefinition;
+use crate::config::environment::Environment;
+use crate::config::launch::Launch;
+use crate::config::readiness::Readiness;
+use crate::config::restart::RestartPolicy;
+use crate::config::stop::StopSettings;
+use crate::config::tty::{TtySize, WindowSize};
+use crate::input::{self, Input, Stdin};
+use crate::output::OutputBuffer;
+use crate::process_group::Descendants;
+use crate::process_status::ProcessStatus;
+use crate::pty::Pty;
+use crate::scrollback::{self, Line, Scrollback};
+use crate::{Message, MultiHostError, process_group};
+
+/// Identifies a hosted process for as long as multi-host runs, however the
+/// process list is reordered or reloaded around it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ProcessId(u32);
+
+impl ProcessId {
+    pub fn next(self) -> Self {
+        ProcessId(self.0 + 1)
+    }
+}
 
-use crate::{Message, MultiHostError, ProcessDefinition};
+/// One run of a hosted process. Tasks from an earlier run can outlive it,
+/// so everything they send says which run it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunId {
+    pub process_id: ProcessId,
+    pub generation: u32,
+}
 
 #[derive(Debug)]
 pub struct HostedProcess {
+    pub id: ProcessId,
+    // how many times the process has been started
+    pub generation: u32,
     pub name: String,
     pub status: ProcessStatus,
-    auto_start_enabled: bool,
-    // todo - this should be a constrained buffer of some kind
-    pub output: String,
-    pub display_name: String,
-    pub child: Option<Arc<Mutex<Child>>>,
-    app: String,
-    args: Vec<String>,
+    pub auto_start_enabled: bool,
+    pub depends_on: Vec<String>,
+    pub readiness: Option<Readiness>,
+    pub restart: RestartPolicy,
+    // restarts in a row since the process last ran for `STABLE_RUN`
+    restart_attempts: u32,
+    started_at: Option<Instant>,
+    pub stop_settings: StopSettings,
+    // start again once the current child has stopped
+    pub restart_after_stop: bool,
+    // where to report things that happen after `start` returns
+    listener: Option<(RunId, Sender<Message>)>,
+    pub output: Scrollback,
+    // show the output without its colours
+    pub strip_color: bool,
+    encoding: &'static Encoding,
+    tty: Option<TtySize>,
+    // the size of the output pane, for a `tty` that follows it
+    pane_size: WindowSize,
+    // the terminal of the current run, if it has one
+    pty: Option<Pty>,
+    // where to send input for the current run to be written to the child
+    stdin: Option<channel::Sender<Input>>,
+    // lines sent to the process, oldest first
+    pub input_history: Vec<String>,
+    child: Option<ChildHandle>,
+    pub launch: Launch,
     working_directory: PathBuf,
+    environment: Environment,
+    // set when a config reload changes a running process's definition
+    pub definition_changed: bool,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum ProcessStatus {
-    NotRun,
-    Running,
-    Stopped,
+/// One of the child's outputs, a pipe or its terminal.
+type OutputReader = Pin<Box<dyn Read + Send>>;
+
+/// A run at least this long resets the restart backoff.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// The UI's side of a child, which belongs to the task waiting for its exit.
+#[derive(Debug, Clone)]
+struct ChildHandle {
+    // nothing is ever sent on this; it closes once the child has exited
+    exited: channel::Receiver<()>,
+    kill: channel::Sender<()>,
+    // what it has started, which can outlive it
+    descendants: Arc<Mutex<Descendants>>,
 }
 
-impl fmt::Display for ProcessStatus {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let status = match self {
-            ProcessStatus::NotRun => "not run",
-            ProcessStatus::Running => "running",
-            ProcessStatus::Stopped => "stopped",
-        };
-        write!(f, "{}", status)
+impl ChildHandle {
+    fn has_exited(&self) -> bool {
+        self.exited.is_closed()
+    }
+
+    /// Whether the child and everything it started have exited, taking a
+    /// fresh look rather than waiting for the next shared one.
+    async fn all_exited(&self) -> bool {
+        self.has_exited() && !self.descendants_alive().await
+    }
+
+    async fn descendants_alive(&self) -> bool {
+        // reading all of /proc blocks
+        let descendants = self.descendants.clone();
+        task::spawn_blocking(move || {
+            let mut descendants = descendants.lock().unwrap();
+            descendants.refresh();
+            descendants.any_alive()
+        })
+        .await
+    }
+
+    /// Returns whether there was anything to kill.
+    fn kill_descendants(&self) -> bool {
+        self.descendants.lock().unwrap().kill()
+    }
+
+    fn kill(&self) {
+        let _ = self.kill.try_send(());
     }
 }
 
 impl fmt::Display for HostedProcess {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.display_name)
+        write!(f, "{} ({})", self.name, self.status)
     }
 }
 
 impl HostedProcess {
-    pub fn new(config: ProcessDefinition) -> HostedProcess {
-        let status = ProcessStatus::NotRun;
-        let display_name = HostedProcess::display_name(config.name.as_str(), &status);
+    pub fn new(id: ProcessId, config: ProcessDefinition) -> HostedProcess {
         Self {
+            id,
+            generation: 0,
             name: config.name,
-            status,
-            output: String::new(),
-            display_name,
+            status: ProcessStatus::NotRun,
+            output: Scrollback::new(config.scrollback),
+            strip_color: config.strip_color,
+            encoding: config.encoding,
+            tty: config.tty,
+            pane_size: WindowSize::default(),
+            pty: None,
+            stdin: None,
+            input_history: vec![],
             child: None,
             auto_start_enabled: config.auto_start,
-            app: config.command,
-            args: config.args,
-            working_directory: PathBuf::from(config.cwd),
+            depends_on: config.depends_on,
+            readiness: config.readiness,
+            restart: config.restart,
+            restart_attempts: 0,
+            started_at: None,
+            stop_settings: config.stop,
+            restart_after_stop: false,
+            listener: None,
+            launch: config.launch,
+            working_directory: config.cwd,
+            environment: config.environment,
+            definition_changed: false,
+        }
+    }
+
+    /// Swaps in a reloaded definition. A running child keeps its old settings
+    /// until it is restarted, so it is flagged as changed until then.
+    pub fn redefine(&mut self, config: ProcessDefinition) {
+        self.auto_start_enabled = config.auto_start;
+        self.depends_on = config.depends_on;
+        self.readiness = config.readiness;
+        self.restart = config.restart;
+        self.stop_settings = config.stop;
+        self.launch = config.launch;
+        self.working_directory = config.cwd;
+        self.environment = config.environment;
+        self.output.set_limits(config.scrollback);
+        self.strip_color = config.strip_color;
+        self.encoding = config.encoding;
+        self.tty = config.tty;
+        self.definition_changed = self.is_alive();
+    }
+
+    /// Keeps the terminal of a `tty` process that follows the output pane
+    /// the same size as it.
+    pub fn pane_resized(&mut self, size: WindowSize) {
+        self.pane_size = size;
+        if self.tty != Some(TtySize::FollowPane) {
+            return;
+        }
+        if let Some(pty) = &self.pty
+            && let Err(e) = pty.resize(size)
+        {
+            writeln!(self.output, "couldn't resize terminal: {}", e)
+                .expect("appending output failed");
+        }
+    }
+
+    /// Whether there is a child to send input to.
+    pub fn accepts_input(&self) -> bool {
+        self.stdin.is_some()
+    }
+
+    /// Sends `input` to the child's stdin. A pipe doesn't echo what's sent
+    /// like a terminal does, so sent lines are noted in the output instead.
+    pub fn send_input(&mut self, input: Input) {
+        let Some(stdin) = &self.stdin else {
+            return;
+        };
+        if let Input::Line(line) = &input {
+            if self.input_history.last() != Some(line) {
+                self.input_history.push(line.clone());
+            }
+            if self.pty.is_none() {
+                writeln!(self.output, "> {}", line).expect("appending output failed");
+            }
+        }
+        let _ = stdin.try_send(input);
+    }
+
+    /// Whether there is a child process that was started and not stopped.
+    pub fn is_alive(&self) -> bool {
+        matches!(
+            self.status,
+            ProcessStatus::Starting { .. } | ProcessStatus::Running { .. }
+        )
+    }
+
+    /// Whether the process is running or on its way to it, as opposed to
+    /// sitting idle until someone starts it.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            ProcessStatus::Waiting { .. }
+                | ProcessStatus::Starting { .. }
+                | ProcessStatus::Running { .. }
+                | ProcessStatus::Stopping { .. }
+                | ProcessStatus::Restarting { .. }
+        )
+    }
+
+    pub fn run_id(&self) -> RunId {
+        RunId {
+            process_id: self.id,
+            generation: self.generation,
+        }
+    }
+
+    /// Forgets earlier crashes, for when the user starts the process afresh.
+    pub fn reset_restarts(&mut self) {
+        self.restart_attempts = 0;
+    }
+
+    /// Moves to the state the child's exit leads to. Returns the delay before
+    /// it should be restarted, if the restart policy wants it restarted.
+    pub fn exited(&mut self, status: ExitStatus, at: Instant) -> Option<Duration> {
+        self.pty = None;
+        self.stdin = None;
+        let stopping = matches!(self.status, ProcessStatus::Stopping { .. });
+        let success = self.restart.is_success(status);
+        self.status = ProcessStatus::after_exit(status, at, stopping, success);
+        if stopping || !self.restart.should_restart(status) {
+            return None;
+        }
+
+        // A clean exit isn't a crash, so it starts the count of restarts in
+        // a row again, as does a long enough run
+        if success
+            || self
+                .started_at
+                .is_some_and(|started| started.elapsed() >= STABLE_RUN)
+        {
+            self.restart_attempts = 0;
         }
+        let since = Instant::now();
+        if !success && self.restart_attempts >= self.restart.max_retries {
+            self.status = ProcessStatus::CrashLooping { since };
+            return None;
+        }
+        self.restart_attempts += 1;
+        self.status = ProcessStatus::Restarting {
+            attempt: self.restart_attempts,
+            since,
+        };
+        Some(self.restart.backoff_for(self.restart_attempts))
     }
 
-    fn update_display_name(&mut self) {
-        self.display_name = HostedProcess::display_name(self.name.as_str(), &self.status);
+    /// Gives up on a child whose exit couldn't be waited for. It counts as
+    /// crashed, but isn't restarted while it might still be running.
+    pub fn lost(&mut self, at: Instant) {
+        self.status = ProcessStatus::Crashed { code: -1, at };
+        self.restart_after_stop = false;
     }
 
-    fn display_name(name: &str, status: &ProcessStatus) -> String {
-        format!("{} ({})", name, status)
+    pub fn restart_attempts(&self) -> u32 {
+        self.restart_attempts
     }
 
-    pub fn run(&mut self) {
-        self.status = ProcessStatus::Running;
-        self.update_display_name();
+    pub fn ready(&mut self) {
+        if let ProcessStatus::Starting { pid, .. } = self.status {
+            self.status = ProcessStatus::Running {
+                pid,
+                since: Instant::now(),
+            };
+        }
     }
 
+    pub fn wait(&mut self) {
+        self.status = ProcessStatus::Waiting {
+            since: Instant::now(),
+        };
+    }
+
+    pub fn failed_to_start(&mut self, reason: String) {
+        self.status = ProcessStatus::FailedToStart {
+            reason,
+            at: Instant::now(),
+        };
+    }
+
+    /// Asks the child to exit, with `stop_command` or the stop signal, and
+    /// kills it if it is still around after the stop timeout. The status
+    /// stays `Stopping` until the exit is seen.
     pub fn stop(&mut self) {
-        self.status = ProcessStatus::Stopped;
-        self.update_display_name();
-
-        match self.child.as_ref() {
-            Some(c) => c
-                .lock()
-                .unwrap()
-                .kill()
-                .expect("child process should be killed"),
-            None => (),
+        let (true, Some(pid)) = (self.is_alive(), self.status.pid()) else {
+            // Nothing is running yet, so there is only the start to cancel
+            if let ProcessStatus::Waiting { .. } | ProcessStatus::Restarting { .. } = self.status {
+                self.status = ProcessStatus::NotRun;
+            }
+            return;
+        };
+        self.status = ProcessStatus::Stopping {
+            pid,
+            since: Instant::now(),
+        };
+
+        let Some(child) = self.child.clone() else {
+            return;
+        };
+        let stop_command = match self.stop_command(pid) {
+            Ok(stop_command) => stop_command,
+            Err(e) => {
+                writeln!(self.output, "couldn't run stop command: {}, killing it", e).unwrap();
+                return self.kill();
+            }
+        };
+        if stop_command.is_none()
+            && let Err(e) = process_group::signal(pid, self.stop_settings.signal)
+        {
+            let signal = self.stop_settings.signal.name();
+            writeln!(self.output, "couldn't send {}: {}, killing it", signal, e).unwrap();
+            return self.kill();
+        }
+
+        task::spawn(HostedProcess::escalate_stop(
+            stop_command,
+            self.stop_settings.timeout,
+            child,
+            self.listener.clone(),
+        ));
+    }
+
+    /// Stops the process and starts it again once it has exited.
+    pub fn restart(&mut self) {
+        self.restart_after_stop = true;
+        self.stop();
+    }
+
+    /// Skips the rest of the grace period.
+    pub fn kill(&mut self) {
+        if let Some(child) = self.child.as_ref() {
+            child.kill_descendants();
+            child.kill();
         }
     }
 
+    /// Warns if processes started by the run are still running after it
+    /// exited, e.g. ones it daemonized or forgot to stop.
+    pub fn warn_about_leftovers(&self) {
+        let Some(child) = self.child.clone() else {
+            return;
+        };
+        let mut listener = self.listener.clone();
+        task::spawn(async move {
+            if child.descendants_alive().await {
+                HostedProcess::report(
+                    &mut listener,
+                    "warning: processes it started are still running".to_string(),
+                )
+                .await;
+            }
+        });
+    }
+
+    /// The `stop_command`, run where the process runs, with the process's id
+    /// in `MULTI_HOST_PID`.
+    fn stop_command(
+        &self,
+        pid: u32,
+    ) -> Result<Option<async_std::process::Command>, MultiHostError> {
+        let Some(launch) = &self.stop_settings.command else {
+            return Ok(None);
+        };
+        let mut cmd = Command::new(launch.program());
+        cmd.args(launch.args());
+        cmd.current_dir(self.working_directory.clone());
+        self.environment.apply(&mut cmd)?;
+        cmd.env("MULTI_HOST_PID", pid.to_string());
+        let mut cmd = async_std::process::Command::from(cmd);
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::null());
+        cmd.stderr(Stdio::null());
+        Ok(Some(cmd))
+    }
+
     pub fn subscribe_to_process_outputs() -> impl Stream<Item = Message> {
         iced::stream::channel(100, |mut output| async move {
             let (sender, mut receiver) = mpsc::channel(100);
@@ -110,25 +441,20 @@ impl HostedProcess {
         })
     }
 
-    pub fn try_auto_start(&mut self, process_id: usize, sender: Sender<Message>) {
-        match self.auto_start_enabled {
-            false => (),
-            true => match self.start(process_id, sender) {
-                Ok(_) => self.run(),
-                Err(e) => writeln!(self.output, "error starting process {:?}", e).unwrap(),
-            },
+    pub fn start(&mut self, sender: Sender<Message>) -> Result<(), MultiHostError> {
+        // so the last run's leftovers don't run alongside this one
+        if let Some(child) = self.child.as_ref()
+            && child.kill_descendants()
+        {
+            writeln!(self.output, "killed processes left over from the last run")
+                .expect("appending output failed");
         }
-    }
 
-    pub fn start(
-        &mut self,
-        process_id: usize,
-        sender: Sender<Message>,
-    ) -> Result<(), MultiHostError> {
-        let mut cmd = Command::new(self.app.clone());
+        let mut cmd = Command::new(self.launch.program());
 
-        cmd.args(self.args.clone());
+        cmd.args(self.launch.args());
         cmd.current_dir(self.working_directory.clone());
+        self.environment.apply(&mut cmd)?;
 
         // todo - support light blue
         // let config_path = format!("{}.config", self.app);
@@ -138,80 +464,182 @@ impl HostedProcess {
         // cmd.env("LightBlueRoleName", self.name.clone());
         // cmd.env("LightBlueUseHostedStorage", "true");
 
-        // todo - make sure the child process is correctly cleanup up on multi-host exit
-        //cmd.kill_on_drop(true);
+        // Multi-host stops its children before quitting; this covers it dying
+        // without the chance to.
+        process_group::die_with_parent(&mut cmd);
+
+        // A `tty` process gets a terminal, and a session of its own; anything
+        // else gets its own process group
+        let pty = match self.tty {
+            Some(size) => {
+                let size = match size {
+                    TtySize::FollowPane => self.pane_size,
+                    TtySize::Fixed(size) => size,
+                };
+                Some(Pty::open(&mut cmd, size)?)
+            }
+            None => {
+                process_group::isolate(&mut cmd);
+                None
+            }
+        };
+        let terminal = match &pty {
+            Some((pty, _)) => Some((pty.reader()?, pty.writer()?)),
+            None => None,
+        };
 
         // Make sure the child process get's it's own pipes for stdio. If we don't
         // do this, the child processes io is piped to the parents - we don't want that.
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-        cmd.stdin(Stdio::piped());
+        let mut cmd = async_std::process::Command::from(cmd);
+        let pty = match pty {
+            Some((pty, [stdin, stdout, stderr])) => {
+                cmd.stdin(stdin);
+                cmd.stdout(stdout);
+                cmd.stderr(stderr);
+                Some(pty)
+            }
+            None => {
+                cmd.stdout(Stdio::piped());
+                cmd.stderr(Stdio::piped());
+                cmd.stdin(Stdio::piped());
+                None
+            }
+        };
 
         let mut child = cmd.spawn()?;
+        // it holds the child's end of the terminal, which has to be closed
+        // for reading the other end to finish when the child exits
+        drop(cmd);
+        let pid = child.id();
+        self.generation += 1;
+        let run = self.run_id();
+
+        // The terminal carries stdout and stderr together, and takes input
+        let (readers, stdin): (Vec<(scrollback::Stream, OutputReader)>, _) = match terminal {
+            Some((reader, writer)) => (
+                vec![(scrollback::Stream::Stdout, Box::pin(reader))],
+                Stdin::Terminal(writer),
+            ),
+            None => {
+                // taken before anything waits on the child, which closes it
+                let stdin = child
+                    .stdin
+                    .take()
+                    .ok_or(MultiHostError::Simple("couldn't take stdin".to_string()))?;
+
+                let stdout = child
+                    .stdout
+                    .take()
+                    .ok_or(MultiHostError::Simple("couldn't take stdout".to_string()))?;
+
+                let stderr = child
+                    .stderr
+                    .take()
+                    .ok_or(MultiHostError::Simple("couldn't take stderr".to_string()))?;
+
+                (
+                    vec![
+                        (scrollback::Stream::Stdout, Box::pin(stdout)),
+                        (scrollback::Stream::Stderr, Box::pin(stderr)),
+                    ],
+                    Stdin::Pipe(stdin),
+                )
+            }
+        };
 
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or(MultiHostError::Simple("couldn't take stdout".to_string()))?;
-
-        let stderr = child
-            .stderr
-            .take()
-            .ok_or(MultiHostError::Simple("couldn't take stderr".to_string()))?;
-
-        let arc_child = Arc::new(Mutex::new(child));
-
-        let exit_child = Arc::clone(&arc_child);
-        let mut exit_sender = sender.clone();
-
-        // Thread to wait on the exit of the child process
-        thread::spawn(move || {
-            block_on(HostedProcess::poll_for_exit_code(
-                process_id,
-                exit_child,
-                &mut exit_sender,
-            ));
-        });
+        let (exited_sender, exited) = channel::bounded(1);
+        let (kill, kill_requests) = channel::bounded(1);
+        let descendants = Descendants::track(pid);
+        let handle = ChildHandle {
+            exited,
+            kill,
+            descendants,
+        };
 
-        // Thread to read the stdout of the child process
+        // Task to wait on the exit of the child process
+        task::spawn(HostedProcess::watch_for_exit(
+            run,
+            child,
+            kill_requests,
+            exited_sender,
+            sender.clone(),
+        ));
+
+        // Tasks to read the stdout and stderr of the child process until they
+        // are closed, and one to pass what they read on to the UI
+        let output = Arc::new(OutputBuffer::new(readers.len()));
+        output.push(scrollback::Stream::Note, "process starting...".to_string());
+        let encoding = self.encoding;
+        for (stream, reader) in readers {
+            let output = Arc::clone(&output);
+            task::spawn(async move { output.read_from(stream, reader, encoding).await });
+        }
         let mut output_sender = sender.clone();
-        thread::spawn(move || {
-            block_on(HostedProcess::poll_for_std_output(
-                process_id,
-                &mut BufReader::new(stdout).lines(),
-                &mut output_sender,
-            ))
-        });
-
-        // Thread to read the stderr of the child process
-        thread::spawn(move || {
-            block_on(HostedProcess::poll_for_std_error(
-                process_id,
-                &mut BufReader::new(stderr).lines(),
-                &mut sender.clone(),
-            ))
-        });
+        task::spawn(async move { output.forward(run, &mut output_sender).await });
+
+        // Task to write what's typed in the input line to the child
+        let (input_sender, inputs) = channel::unbounded();
+        task::spawn(input::write_to(stdin, pid, run, inputs, sender.clone()));
+
+        // Task to poll the readiness check, unless it watches the output
+        if let Some(readiness) = self.readiness.clone()
+            && !readiness.check.is_log()
+        {
+            task::spawn(HostedProcess::poll_for_readiness(
+                run,
+                readiness,
+                self.working_directory.clone(),
+                self.environment.clone(),
+                handle.clone(),
+                sender.clone(),
+            ));
+        }
 
-        self.child = Some(arc_child);
+        self.child = Some(handle);
+        self.pty = pty;
+        self.stdin = Some(input_sender);
+        self.started_at = Some(Instant::now());
+        self.listener = Some((run, sender));
+        self.definition_changed = false;
+        self.restart_after_stop = false;
+
+        // It only counts as running once its readiness check passes
+        let since = Instant::now();
+        self.status = match self.readiness {
+            Some(_) => ProcessStatus::Starting { pid, since },
+            None => ProcessStatus::Running { pid, since },
+        };
 
         Ok(())
     }
 
-    pub fn to_element(&self, process_id: usize, is_focused: bool) -> iced::Element<Message> {
+    pub fn to_element(&self, is_focused: bool) -> iced::Element<'_, Message> {
+        let process_id = self.id;
         let action_button = match self.status {
-            ProcessStatus::NotRun | ProcessStatus::Stopped => button("start")
-                .style(button::success)
+            ProcessStatus::Waiting { .. } | ProcessStatus::Restarting { .. } => button("cancel")
+                .style(button::secondary)
+                .on_press(Message::StartStopProcess(process_id)),
+            ProcessStatus::Starting { .. } | ProcessStatus::Running { .. } => button("stop")
+                .style(button::danger)
                 .on_press(Message::StartStopProcess(process_id)),
-            ProcessStatus::Running => button("stop")
+            ProcessStatus::Stopping { .. } => button("kill")
                 .style(button::danger)
                 .on_press(Message::StartStopProcess(process_id)),
+            _ => button("start")
+                .style(button::success)
+                .on_press(Message::StartStopProcess(process_id)),
         };
+        let mut label = self.name.clone();
+        if let Some(status) = self.status.short() {
+            label = format!("{} ({})", label, status);
+        }
+        if self.definition_changed {
+            label.push_str(" (changed)");
+        }
         row![
             match is_focused {
-                true => button(self.name.as_str())
-                    .style(button::primary)
-                    .width(Fill),
-                false => button(self.name.as_str())
+                true => button(text(label)).style(button::primary).width(Fill),
+                false => button(text(label))
                     .style(button::primary)
                     .width(Fill)
                     .on_press(Message::FocusProcess(process_id)),
@@ -221,92 +649,254 @@ impl HostedProcess {
         .into()
     }
 
-    async fn poll_for_std_output(
-        process_id: usize,
-        stdout_reader: &mut Lines<BufReader<ChildStdout>>,
-        output: &mut Sender<Message>,
+    async fn poll_for_readiness(
+        run: RunId,
+        readiness: Readiness,
+        working_directory: PathBuf,
+        environment: Environment,
+        child: ChildHandle,
+        mut output: Sender<Message>,
     ) {
-        let _ = output
-            .send(Message::ProcessOutput(
-                process_id,
-                "process starting...".to_string(),
-            ))
-            .await;
-
         loop {
-            let stdout_ok: bool = match stdout_reader.next() {
-                Some(result) => output
-                    .send(Message::ProcessOutput(
-                        process_id,
-                        result.unwrap_or_else(|e| e.to_string()),
-                    ))
-                    .await
-                    .is_ok(),
-                _ => true,
-            };
+            task::sleep(readiness.interval).await;
 
-            if stdout_ok == false {
+            if child.has_exited() {
+                break;
+            }
+            // the checks connect and run commands, which block
+            let check = readiness.check.clone();
+            let working_directory = working_directory.clone();
+            let environment = environment.clone();
+            let ready =
+                task::spawn_blocking(move || check.poll(&working_directory, &environment)).await;
+            if ready {
+                let _ = output.send(Message::ProcessReady(run)).await;
                 break;
-            } else {
-                thread::sleep(Duration::from_secs(1));
             }
         }
     }
 
-    async fn poll_for_std_error(
-        process_id: usize,
-        stderr_reader: &mut Lines<BufReader<ChildStderr>>,
-        output: &mut Sender<Message>,
+    /// Waits for the process and everything it started to exit, and
+    /// SIGKILLs them all if that takes longer than `timeout`.
+    async fn escalate_stop(
+        stop_command: Option<async_std::process::Command>,
+        timeout: Duration,
+        child: ChildHandle,
+        mut listener: Option<(RunId, Sender<Message>)>,
     ) {
-        loop {
-            let stderr_ok: bool = match stderr_reader.next() {
-                Some(err) => output
-                    .send(Message::ProcessOutput(
-                        process_id,
-                        err.unwrap_or_else(|e| e.to_string()),
-                    ))
-                    .await
-                    .is_ok(),
-                _ => true,
+        let deadline = Instant::now() + timeout;
+
+        // The stop command gets the same time as the process, so one that
+        // hangs can't hold off the SIGKILL
+        if let Some(mut stop_command) = stop_command {
+            let problem = match stop_command.spawn() {
+                Ok(mut stop_child) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    match future::timeout(left, stop_child.status()).await {
+                        Ok(Ok(status)) if status.success() => None,
+                        Ok(Ok(status)) => Some(format!("stop command failed ({})", status)),
+                        Ok(Err(e)) => Some(format!("couldn't wait for stop command: {}", e)),
+                        Err(_) => {
+                            let _ = stop_child.kill();
+                            Some(format!(
+                                "stop command still running after {:?}, killing it",
+                                timeout
+                            ))
+                        }
+                    }
+                }
+                Err(e) => Some(format!("couldn't run stop command: {}", e)),
             };
+            if let Some(problem) = problem {
+                HostedProcess::report(&mut listener, problem).await;
+            }
+        }
 
-            if stderr_ok == false {
+        loop {
+            if child.all_exited().await {
+                return;
+            }
+            if Instant::now() >= deadline {
                 break;
-            } else {
-                thread::sleep(Duration::from_secs(1));
             }
+            task::sleep(Duration::from_millis(100)).await;
+        }
+
+        HostedProcess::report(
+            &mut listener,
+            format!("still running after {:?}, killing it", timeout),
+        )
+        .await;
+        child.kill_descendants();
+        child.kill();
+
+        // SIGKILL can't be ignored, but a process stuck in the kernel can
+        // still outlive it, as can one started after the last look
+        task::sleep(Duration::from_millis(500)).await;
+        if !child.all_exited().await {
+            HostedProcess::report(
+                &mut listener,
+                "warning: processes it started survived being killed".to_string(),
+            )
+            .await;
         }
     }
 
-    async fn poll_for_exit_code(
-        process_id: usize,
-        child: Arc<Mutex<Child>>,
-        output: &mut Sender<Message>,
+    async fn report(listener: &mut Option<(RunId, Sender<Message>)>, line: String) {
+        if let Some((run, sender)) = listener.as_mut() {
+            let line = Line::new(scrollback::Stream::Note, line);
+            let _ = sender.send(Message::ProcessOutput(*run, vec![line])).await;
+        }
+    }
+
+    /// Owns the child until it exits, killing it when asked to, and reports
+    /// the exit as soon as it happens.
+    async fn watch_for_exit(
+        run: RunId,
+        mut child: Child,
+        kill_requests: channel::Receiver<()>,
+        exited: channel::Sender<()>,
+        mut output: Sender<Message>,
     ) {
-        loop {
-            let exit = {
-                let mut child = child.lock().unwrap();
-                match child.try_wait() {
-                    Ok(optional_status) => match optional_status {
-                        Some(status) => {
-                            output
-                                .send(Message::ProcessOutput(
-                                    process_id,
-                                    format!("process exited with code {}", status),
-                                ))
-                                .await
-                                .unwrap();
-                            Some(status)
-                        }
-                        None => None,
-                    },
-                    Err(_) => panic!("oh no"),
+        // Waiting on the child closes its stdin, which some processes take as
+        // their cue to exit
+        let _stdin = child.stdin.take();
+
+        let status = loop {
+            let exit = async { Some(child.status().await) };
+            let kill = async {
+                match kill_requests.recv().await {
+                    Ok(()) => None,
+                    // the process is gone from the UI, so nobody will ask
+                    Err(_) => future::pending().await,
                 }
             };
-            match exit {
-                Some(_) => break,
-                None => thread::sleep(Duration::from_secs(1)),
+            match exit.race(kill).await {
+                Some(status) => break status,
+                None => {
+                    let _ = child.kill();
+                }
             }
+        };
+        let at = Instant::now();
+        drop(exited);
+
+        let message = match status {
+            Ok(status) => Message::ProcessExited(run, status, at),
+            Err(e) => Message::ProcessLost(run, e.to_string(), at),
+        };
+        let _ = output.send(message).await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::restart::RestartWhen;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn code(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn process(when: RestartWhen, max_retries: u32) -> HostedProcess {
+        let mut definition = ProcessDefinition::named("web");
+        definition.restart.when = when;
+        definition.restart.max_retries = max_retries;
+        HostedProcess::new(ProcessId::default(), definition)
+    }
+
+    /// Starts the process over, as `start` would, without a child.
+    fn run(process: &mut HostedProcess) {
+        process.status = ProcessStatus::Running {
+            pid: 1,
+            since: Instant::now(),
+        };
+    }
+
+    #[test]
+    fn failures_back_off_until_it_is_a_crash_loop() {
+        let mut process = process(RestartWhen::OnFailure, 2);
+
+        run(&mut process);
+        assert_eq!(
+            process.exited(code(1), Instant::now()),
+            Some(Duration::from_secs(1))
+        );
+        assert!(matches!(
+            process.status,
+            ProcessStatus::Restarting { attempt: 1, .. }
+        ));
+
+        run(&mut process);
+        assert_eq!(
+            process.exited(code(1), Instant::now()),
+            Some(Duration::from_secs(2))
+        );
+
+        run(&mut process);
+        assert_eq!(process.exited(code(1), Instant::now()), None);
+        assert!(matches!(process.status, ProcessStatus::CrashLooping { .. }));
+    }
+
+    #[test]
+    fn clean_exits_never_make_a_crash_loop() {
+        let mut process = process(RestartWhen::Always, 2);
+        for _ in 0..5 {
+            run(&mut process);
+            assert_eq!(
+                process.exited(code(0), Instant::now()),
+                Some(Duration::from_secs(1))
+            );
         }
+        assert!(matches!(
+            process.status,
+            ProcessStatus::Restarting { attempt: 1, .. }
+        ));
+
+        // the count goes on from the last of them
+        run(&mut process);
+        assert_eq!(
+            process.exited(code(1), Instant::now()),
+            Some(Duration::from_secs(2))
+        );
+        run(&mut process);
+        assert_eq!(process.exited(code(1), Instant::now()), None);
+        assert!(matches!(process.status, ProcessStatus::CrashLooping { .. }));
+    }
+
+    #[test]
+    fn clean_exit_is_restarted_even_with_no_retries() {
+        let mut process = process(RestartWhen::Always, 0);
+        run(&mut process);
+        assert_eq!(
+            process.exited(code(0), Instant::now()),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn clean_exit_without_restart() {
+        let mut process = process(RestartWhen::OnFailure, 2);
+        run(&mut process);
+        assert_eq!(process.exited(code(0), Instant::now()), None);
+        assert!(matches!(
+            process.status,
+            ProcessStatus::Exited { code: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn stopped_process_is_not_restarted() {
+        let mut process = process(RestartWhen::Always, 2);
+        process.status = ProcessStatus::Stopping {
+            pid: 1,
+            since: Instant::now(),
+        };
+        assert_eq!(process.exited(code(143), Instant::now()), None);
+        assert!(matches!(
+            process.status,
+            ProcessStatus::Exited { code: 143, .. }
+        ));
     }
 }
@@ -0,0 +1,91 @@
+//! What's typed into the input line under the output pane goes to the
+//! focused process's stdin, for processes that take commands on the console
+//! like REPLs and dev servers. A task per run owns the child's stdin and
+//! writes to it, so a child that's slow to read never holds up the UI.
+
+use async_std::channel::Receiver;
+use async_std::io::{self, Write, WriteExt};
+use async_std::process::ChildStdin;
+use iced::futures::SinkExt;
+use iced::futures::channel::mpsc::Sender;
+
+use crate::Message;
+use crate::config::stop::StopSignal;
+use crate::hosted_process::RunId;
+use crate::process_group;
+use crate::pty::PtyWriter;
+use crate::scrollback::{Line, Stream};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    /// A line of text, sent with a newline as if typed and entered.
+    Line(String),
+    /// End of input, as Ctrl+D would send it in a terminal.
+    Eof,
+    /// Interrupt the process, as Ctrl+C would in a terminal.
+    Interrupt,
+}
+
+/// Which way through the history the up and down arrows go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryStep {
+    Older,
+    Newer,
+}
+
+/// The child's end of the input.
+pub enum Stdin {
+    Pipe(ChildStdin),
+    /// Its terminal, which turns control characters into EOF and signals
+    /// for whatever is in the foreground.
+    Terminal(PtyWriter),
+}
+
+/// Writes what arrives on `inputs` to the process led by `pid`, until the
+/// run is over or its input is closed.
+pub async fn write_to(
+    stdin: Stdin,
+    pid: u32,
+    run: RunId,
+    inputs: Receiver<Input>,
+    mut output: Sender<Message>,
+) {
+    let mut stdin = Some(stdin);
+    while let Ok(input) = inputs.recv().await {
+        let result = match input {
+            Input::Line(line) => match &mut stdin {
+                Some(Stdin::Pipe(pipe)) => write(pipe, &format!("{}\n", line)).await,
+                // a terminal is sent what the enter key sends
+                Some(Stdin::Terminal(terminal)) => write(terminal, &format!("{}\r", line)).await,
+                None => Err(closed()),
+            },
+            Input::Eof => match stdin.take() {
+                // closing the pipe is how its end is reached
+                Some(Stdin::Pipe(_)) => Ok(()),
+                Some(Stdin::Terminal(mut terminal)) => {
+                    let result = write(&mut terminal, "\x04").await;
+                    stdin = Some(Stdin::Terminal(terminal));
+                    result
+                }
+                None => Err(closed()),
+            },
+            Input::Interrupt => match &mut stdin {
+                Some(Stdin::Terminal(terminal)) => write(terminal, "\x03").await,
+                _ => process_group::signal(pid, StopSignal::Int),
+            },
+        };
+        if let Err(e) = result {
+            let line = Line::new(Stream::Note, format!("couldn't send input: {}", e));
+            let _ = output.send(Message::ProcessOutput(run, vec![line])).await;
+        }
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "input was already closed")
+}
+
+async fn write(stdin: &mut (impl Write + Unpin), text: &str) -> io::Result<()> {
+    stdin.write_all(text.as_bytes()).await?;
+    stdin.flush().await
+}
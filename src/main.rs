@@ -1,74 +1,56 @@
 //#![windows_subsystem = "windows"]
 // Uncomment the above before release. Prevents stupid console window.
 
+use config::Configuration;
+use config::diagnostic::Diagnostics;
+use config::diff::ConfigDiff;
+use hosted_process::{ProcessId, RunId};
+use iced::widget::scrollable;
 use iced::{Element, Subscription, Task, Theme, futures::channel::mpsc::Sender};
+use input::{HistoryStep, Input};
 use screens::home::HomeScreen;
 use screens::settings::SettingsScreen;
-use std::fs::File;
-use std::io::Read;
+use scrollback::{Line, StreamFilter};
+use std::path::PathBuf;
+use std::process::ExitStatus;
 use std::rc::Rc;
-use std::{env, io};
+use std::time::Instant;
+use std::{env, io, process};
 use thiserror::Error;
-use yaml_rust2::YamlLoader;
 
+mod ansi;
+mod config;
 mod hosted_process;
+mod input;
+mod output;
+mod process_group;
+mod process_status;
+mod pty;
 mod screens;
+mod scrollback;
+mod shutdown;
 
-fn main() -> iced::Result {
-    let args: Vec<_> = env::args().collect();
-    let mut f = File::open(&args[1]).unwrap();
-    let mut s = String::new();
-    f.read_to_string(&mut s).unwrap();
-    let docs = YamlLoader::load_from_str(s.as_str()).unwrap();
-    let doc = &docs[0];
-
-    let process_list = doc["process"].clone();
-    let mut processes: Vec<ProcessDefinition> = vec![];
-    for process_input in process_list.into_iter() {
-        processes.push(ProcessDefinition {
-            name: process_input["name"]
-                .as_str()
-                .unwrap_or("unknown")
-                .to_string(),
-            command: process_input["exe"]
-                .as_str()
-                .expect("process should have exe")
-                .to_string(),
-            cwd: process_input["cwd"]
-                .as_str()
-                .expect("process should have cwd")
-                .to_string(),
-            args: process_input["args"]
-                .clone()
-                .into_iter()
-                .map(|i| i.as_str().expect("process should have args").to_string())
-                .collect(),
-            auto_start: process_input["auto_start"].as_bool().unwrap_or(false),
-        });
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
     }
+}
 
-    let config = Configuration {
-        processes: Rc::new(processes),
-    };
+fn run() -> Result<(), MultiHostError> {
+    let config_path = env::args().nth(1).ok_or(MultiHostError::Usage)?;
+    let config_path = PathBuf::from(config_path);
+    let config = config::load(&config_path)?;
+    shutdown::install_handlers();
 
     iced::application("Multi-Host", MultiHost::update, MultiHost::view)
         .theme(MultiHost::theme)
         .subscription(MultiHost::subscription)
-        .run_with(|| (MultiHost::new(config), iced::Task::none()))
-}
+        // closing the window has to stop the hosted processes first
+        .exit_on_close_request(false)
+        .run_with(|| (MultiHost::new(config_path, config), iced::Task::none()))?;
 
-#[derive(Debug)]
-struct Configuration {
-    processes: Rc<Vec<ProcessDefinition>>,
-}
-
-#[derive(Debug, Clone)]
-struct ProcessDefinition {
-    name: String,
-    command: String,
-    cwd: String,
-    args: Vec<String>,
-    auto_start: bool,
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -77,7 +59,12 @@ struct MultiHost {
     home_screen: HomeScreen,
     settings_screen: SettingsScreen,
     output_listener: Option<Sender<Message>>,
-    configuration: Rc<Configuration>,
+    // messages that came before the output listener, to handle once it's there
+    waiting_for_listener: Vec<Message>,
+    config_path: PathBuf,
+    configuration: Configuration,
+    // a reloaded config waiting for the user to confirm the preview
+    pending_configuration: Option<Configuration>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -91,25 +78,66 @@ enum Message {
     ChangeScreen(Screen),
     SaveSettings,
     SettingsSettingOneUpdated(String),
-    ProcessOutput(usize, String),
-    StartStopProcess(usize),
+    ProcessOutput(RunId, Vec<Line>),
+    // lines a run wrote that were dropped before reaching the UI
+    OutputDropped(RunId, u64),
+    StartStopProcess(ProcessId),
+    StartWithDependencies(ProcessId),
+    StartWithoutDependencies(ProcessId),
+    DismissDependencyPrompt,
+    StopAllProcesses,
+    ProcessReady(RunId),
+    ProcessExited(RunId, ExitStatus, Instant),
+    // waiting for the child's exit failed with this error
+    ProcessLost(RunId, String, Instant),
+    RestartProcess(RunId),
+    CloseRequested,
+    ShutdownSignal,
+    ConfirmQuit,
+    CancelQuit,
+    ForceQuit,
     ListeningForOutput(Sender<Message>),
-    FocusProcess(usize),
+    FocusProcess(ProcessId),
     AutoStartProcesses(Sender<Message>),
     ToggleHomeSideBar,
+    LogScrolled(scrollable::Viewport),
+    // the output pane was laid out at a new size
+    PaneResized(iced::Size),
+    ShowStreams(StreamFilter),
+    InputChanged(String),
+    SubmitInput,
+    SendInput(Input),
+    // an arrow key pressed, which only steps through the history if the
+    // input line has focus
+    HistoryKeyPressed(HistoryStep),
+    InputHistory(HistoryStep),
+    ConfigFileChanged,
+    ApplyConfigReload,
+    DismissConfigReload,
 }
 
 impl MultiHost {
-    fn new(config: Configuration) -> Self {
+    fn new(config_path: PathBuf, config: Configuration) -> Self {
         Self {
             current_screen: Screen::Home,
-            home_screen: HomeScreen::new(Rc::clone(&config.processes)),
-            configuration: Rc::new(config),
+            home_screen: HomeScreen::new(Rc::clone(&config.processes), config.scrollback.total),
             settings_screen: SettingsScreen::new(),
             output_listener: None,
+            waiting_for_listener: vec![],
+            config_path,
+            configuration: config,
+            pending_configuration: None,
         }
     }
 
+    /// Keeps a message that needs the output listener until it is there,
+    /// rather than dropping it.
+    fn wait_for_listener(&mut self, message: Message) -> Task<Message> {
+        println!("not listening for output yet, holding on to {:?}", message);
+        self.waiting_for_listener.push(message);
+        Task::none()
+    }
+
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::ChangeScreen(screen) => {
@@ -118,25 +146,147 @@ impl MultiHost {
             }
             Message::StartStopProcess(process_id) => match &self.output_listener {
                 Some(listener) => self.home_screen.start_stop(process_id, listener),
-                None => panic!("oh no"),
+                None => self.wait_for_listener(Message::StartStopProcess(process_id)),
+            },
+            Message::StartWithDependencies(process_id) => match &self.output_listener {
+                Some(listener) => self
+                    .home_screen
+                    .start_with_dependencies(process_id, listener),
+                None => self.wait_for_listener(Message::StartWithDependencies(process_id)),
+            },
+            Message::StartWithoutDependencies(process_id) => match &self.output_listener {
+                Some(listener) => self
+                    .home_screen
+                    .start_without_dependencies(process_id, listener),
+                None => self.wait_for_listener(Message::StartWithoutDependencies(process_id)),
+            },
+            Message::DismissDependencyPrompt => self.home_screen.dismiss_dependency_prompt(),
+            Message::StopAllProcesses => self.home_screen.stop_all(),
+            Message::ProcessExited(run, status, at) => match &self.output_listener {
+                Some(listener) => {
+                    let task = self.home_screen.process_exited(run, status, at, listener);
+                    Task::batch([task, self.quit_when_stopped()])
+                }
+                None => self.wait_for_listener(Message::ProcessExited(run, status, at)),
+            },
+            Message::ProcessLost(run, error, at) => {
+                let task = self.home_screen.process_lost(run, error, at);
+                Task::batch([task, self.quit_when_stopped()])
+            }
+            Message::CloseRequested => match self.home_screen.has_live_processes() {
+                false => iced::exit(),
+                true if self.home_screen.is_quitting() => Task::none(),
+                true => {
+                    self.current_screen = Screen::Home;
+                    self.home_screen.confirm_quit()
+                }
+            },
+            // Whoever sends a signal isn't looking at the window, so don't ask.
+            // A second one means they don't want to wait either.
+            Message::ShutdownSignal => match self.home_screen.is_quitting() {
+                true => self.force_quit(),
+                false => {
+                    let task = self.home_screen.begin_quit();
+                    Task::batch([task, self.quit_when_stopped()])
+                }
+            },
+            Message::ConfirmQuit => {
+                let task = self.home_screen.begin_quit();
+                Task::batch([task, self.quit_when_stopped()])
+            }
+            Message::CancelQuit => self.home_screen.cancel_quit(),
+            Message::ForceQuit => self.force_quit(),
+            Message::RestartProcess(run) => match &self.output_listener {
+                Some(listener) => self.home_screen.restart_process(run, listener),
+                None => self.wait_for_listener(Message::RestartProcess(run)),
+            },
+            Message::ProcessReady(run) => match &self.output_listener {
+                Some(listener) => self.home_screen.process_ready(run, listener),
+                None => self.wait_for_listener(Message::ProcessReady(run)),
             },
             Message::ToggleHomeSideBar => self.home_screen.toggle_side_bar(),
+            Message::LogScrolled(viewport) => self.home_screen.log_scrolled(viewport),
+            Message::PaneResized(size) => self.home_screen.pane_resized(size),
+            Message::ShowStreams(filter) => self.home_screen.show_streams(filter),
+            Message::InputChanged(input) => self.home_screen.input_changed(input),
+            Message::SubmitInput => self.home_screen.submit_input(),
+            Message::SendInput(input) => self.home_screen.send_input(input),
+            Message::HistoryKeyPressed(step) => self.home_screen.history_key_pressed(step),
+            Message::InputHistory(step) => self.home_screen.input_history(step),
             Message::AutoStartProcesses(sender) => self.home_screen.auto_start(&sender.clone()),
             Message::FocusProcess(process_id) => self.home_screen.focus(process_id),
-            Message::ProcessOutput(_, _) => self.home_screen.update(message),
+            Message::ProcessOutput(_, _) | Message::OutputDropped(_, _) => {
+                self.home_screen.update(message)
+            }
             Message::SettingsSettingOneUpdated(_) | Message::SaveSettings => {
                 self.settings_screen.update(message)
             }
+            Message::ConfigFileChanged => self.reload_config(),
+            Message::ApplyConfigReload => match self.pending_configuration.take() {
+                Some(config) => {
+                    self.home_screen
+                        .set_scrollback_budget(config.scrollback.total);
+                    let task = self
+                        .home_screen
+                        .apply_reload(config.reload.restart_changed, self.output_listener.as_ref());
+                    self.configuration = config;
+                    task
+                }
+                None => Task::none(),
+            },
+            Message::DismissConfigReload => {
+                self.pending_configuration = None;
+                self.home_screen.dismiss_reload()
+            }
             Message::ListeningForOutput(sender) => {
                 println!("listening for output, about to signal auto start");
                 let message = Message::AutoStartProcesses(sender.clone());
                 self.output_listener = Some(sender);
-                Task::done(message)
+                let waiting = self.waiting_for_listener.drain(..).map(Task::done);
+                Task::batch(std::iter::once(Task::done(message)).chain(waiting))
+            }
+        }
+    }
+
+    fn quit_when_stopped(&self) -> Task<Message> {
+        match self.home_screen.is_quitting() && !self.home_screen.has_live_processes() {
+            true => iced::exit(),
+            false => Task::none(),
+        }
+    }
+
+    fn force_quit(&mut self) -> Task<Message> {
+        self.home_screen.kill_all();
+        iced::exit()
+    }
+
+    fn reload_config(&mut self) -> Task<Message> {
+        match config::load(&self.config_path) {
+            Ok(config) => {
+                let diff = ConfigDiff::new(&self.configuration.processes, &config.processes);
+                match diff.is_empty() {
+                    // Nothing a process cares about changed, so there is nothing to confirm
+                    true => {
+                        self.home_screen
+                            .set_scrollback_budget(config.scrollback.total);
+                        self.configuration = config;
+                        self.pending_configuration = None;
+                        self.home_screen.dismiss_reload()
+                    }
+                    false => {
+                        self.pending_configuration = Some(config);
+                        self.home_screen.show_reload_preview(diff)
+                    }
+                }
+            }
+            Err(e) => {
+                self.pending_configuration = None;
+                self.home_screen.show_reload_error(e.to_string())
             }
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         match self.current_screen {
             Screen::Home => self.home_screen.view(),
             Screen::Settings => self.settings_screen.view(),
@@ -144,7 +294,15 @@ impl MultiHost {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        let subs = self.home_screen.subscription();
+        let mut subs = self.home_screen.subscription();
+        subs.push(iced::window::close_requests().map(|_| Message::CloseRequested));
+        subs.push(Subscription::run(shutdown::signals));
+        if self.configuration.reload.watch {
+            subs.push(Subscription::run_with_id(
+                self.configuration.sources.clone(),
+                config::watch(self.configuration.sources.clone()),
+            ));
+        }
         Subscription::batch(subs)
     }
 
@@ -161,6 +319,21 @@ enum MultiHostError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
 
+    #[error("usage: multi-host <config.yaml>")]
+    Usage,
+
+    #[error("could not read config file `{}`: {source}", path.display())]
+    ConfigRead { path: PathBuf, source: io::Error },
+
+    #[error("invalid config:\n{0}")]
+    Config(#[from] Diagnostics),
+
+    #[error("could not read env file `{}`: {source}", path.display())]
+    EnvFileRead { path: PathBuf, source: io::Error },
+
+    #[error("invalid env file:\n{0}")]
+    EnvFile(Diagnostics),
+
     #[error("Simple error: `{0}`")]
     Simple(String),
 }
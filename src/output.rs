@@ -0,0 +1,293 @@
+//! A run's stdout and stderr are read as fast as the child writes them, so
+//! its pipes never fill up and block it. The lines are handed to the UI in
+//! batches, at most one message per frame, however chatty the process is.
+//! If the UI falls behind, the oldest lines waiting for it are dropped.
+
+use async_std::channel;
+use async_std::future;
+use async_std::io::{self, Read, ReadExt};
+use async_std::task;
+use encoding_rs::Encoding;
+use iced::futures::SinkExt;
+use iced::futures::channel::mpsc::Sender;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Message;
+use crate::ansi;
+use crate::hosted_process::RunId;
+use crate::scrollback::{Line, Stream};
+
+/// How often collected output is sent to the UI.
+const FRAME: Duration = Duration::from_millis(16);
+
+/// How long a line without its newline waits before it is shown anyway, for
+/// prompts and progress bars.
+const IDLE: Duration = Duration::from_millis(100);
+
+/// How many lines may wait for the UI before the oldest are dropped.
+const MAX_PENDING: usize = 10_000;
+
+/// Lines read since the last batch was sent.
+#[derive(Debug)]
+pub struct OutputBuffer {
+    pending: Mutex<Pending>,
+    // readers that haven't reached the end of their pipe yet
+    open_readers: AtomicUsize,
+    // wakes `forward` when there's something for it to do
+    wakeup: channel::Sender<()>,
+    woken: channel::Receiver<()>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    lines: VecDeque<Line>,
+    // finished lines dropped to stay within `MAX_PENDING`
+    dropped: u64,
+}
+
+impl OutputBuffer {
+    pub fn new(readers: usize) -> Self {
+        let (wakeup, woken) = channel::bounded(1);
+        Self {
+            pending: Mutex::new(Pending::default()),
+            open_readers: AtomicUsize::new(readers),
+            wakeup,
+            woken,
+        }
+    }
+
+    pub fn push(&self, stream: Stream, text: String) {
+        self.push_line(Line::new(stream, text));
+    }
+
+    fn push_line(&self, line: Line) {
+        let mut pending = self.pending.lock().unwrap();
+        // a partial line only needs to reach the UI in its latest form
+        if line.partial
+            && let Some(last) = pending.lines.back_mut()
+            && last.partial
+            && last.stream == line.stream
+        {
+            *last = line;
+        } else {
+            pending.lines.push_back(line);
+        }
+        while pending.lines.len() > MAX_PENDING {
+            // a partial line is followed by what it turned into, so isn't lost
+            if pending.lines.pop_front().is_some_and(|line| !line.partial) {
+                pending.dropped += 1;
+            }
+        }
+        drop(pending);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        // a full channel means `forward` is already due to wake
+        let _ = self.wakeup.try_send(());
+    }
+
+    /// Reads `reader`, the child's `stream`, into the buffer until the pipe
+    /// is closed. Anything that can't be decoded as `encoding` comes out as
+    /// U+FFFD, and a line without its newline yet is shown once nothing more
+    /// has come for `IDLE`.
+    pub async fn read_from(
+        &self,
+        stream: Stream,
+        mut reader: impl Read + Unpin,
+        encoding: &'static Encoding,
+    ) {
+        let mut decoder = encoding.new_decoder();
+        let mut parser = ansi::Parser::default();
+        let mut bytes = vec![0; 8 * 1024];
+        let mut text = String::new();
+        // whether anything has been written since the last newline, and when
+        // that partial line was first written to since it was last shown
+        let mut started = false;
+        let mut unshown: Option<Instant> = None;
+
+        loop {
+            let read = match unshown {
+                Some(since) => {
+                    let idle = IDLE.saturating_sub(since.elapsed());
+                    future::timeout(idle, reader.read(&mut bytes)).await
+                }
+                None => Ok(reader.read(&mut bytes).await),
+            };
+            let read = match read {
+                Ok(Ok(read)) => read,
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(Err(e)) => {
+                    self.push(Stream::Note, format!("error reading output: {}", e));
+                    break;
+                }
+                Err(_) => {
+                    let (text, styles) = parser.line();
+                    let line = Line {
+                        partial: true,
+                        ..Line::styled(stream, text, styles)
+                    };
+                    self.push_line(line);
+                    unshown = None;
+                    continue;
+                }
+            };
+
+            text.clear();
+            let last = read == 0;
+            let capacity = decoder.max_utf8_buffer_length(read).unwrap_or(read * 3);
+            text.reserve(capacity);
+            let _ = decoder.decode_to_string(&bytes[..read], &mut text, last);
+
+            let mut pieces = text.split('\n');
+            if let Some(first) = pieces.next() {
+                parser.write(first);
+            }
+            for piece in pieces {
+                let (line, styles) = parser.end_line();
+                self.push_line(Line::styled(stream, line, styles));
+                (started, unshown) = (false, None);
+                parser.write(piece);
+            }
+            if !text.is_empty() && !text.ends_with('\n') {
+                started = true;
+                unshown.get_or_insert_with(Instant::now);
+            }
+
+            if last {
+                break;
+            }
+        }
+
+        // the last line doesn't need a newline to be finished
+        if started {
+            let (line, styles) = parser.end_line();
+            self.push_line(Line::styled(stream, line, styles));
+        }
+        self.open_readers.fetch_sub(1, Ordering::SeqCst);
+        self.wake();
+    }
+
+    /// Sends what has been read as it comes, at most once per frame, until
+    /// every reader is done and the last of it has been sent.
+    pub async fn forward(&self, run: RunId, output: &mut Sender<Message>) {
+        while self.woken.recv().await.is_ok() {
+            // checked first, so nothing pushed before the readers finished is missed
+            let finished = self.open_readers.load(Ordering::SeqCst) == 0;
+            let Pending { lines, dropped } = mem::take(&mut *self.pending.lock().unwrap());
+            if dropped > 0
+                && output
+                    .send(Message::OutputDropped(run, dropped))
+                    .await
+                    .is_err()
+            {
+                break;
+            }
+            if !lines.is_empty()
+                && output
+                    .send(Message::ProcessOutput(run, lines.into()))
+                    .await
+                    .is_err()
+            {
+                break;
+            }
+            if finished {
+                break;
+            }
+            task::sleep(FRAME).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::Cursor;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    fn read(bytes: &[u8], encoding: &'static Encoding) -> Vec<Line> {
+        let buffer = OutputBuffer::new(1);
+        task::block_on(buffer.read_from(Stream::Stdout, Cursor::new(bytes.to_vec()), encoding));
+        mem::take(&mut buffer.pending.lock().unwrap().lines).into()
+    }
+
+    fn texts(lines: &[Line]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    /// Gives out its bytes, then never anything more, like a process
+    /// sitting at a prompt.
+    struct Stalled(Option<Vec<u8>>);
+
+    impl Read for Stalled {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            match self.0.take() {
+                Some(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Poll::Ready(Ok(bytes.len()))
+                }
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let lines = read(b"ok \xff\xfe done\n", encoding_rs::UTF_8);
+        assert_eq!(texts(&lines), ["ok \u{fffd}\u{fffd} done"]);
+    }
+
+    #[test]
+    fn other_encodings_are_decoded() {
+        let lines = read(b"caf\xe9\n", encoding_rs::WINDOWS_1252);
+        assert_eq!(texts(&lines), ["café"]);
+    }
+
+    #[test]
+    fn trailing_line_without_newline_is_finished_at_the_end() {
+        let lines = read(b"first\nsecond", encoding_rs::UTF_8);
+        assert_eq!(texts(&lines), ["first", "second"]);
+        assert!(lines.iter().all(|line| !line.partial));
+    }
+
+    #[test]
+    fn carriage_returns_across_lines() {
+        let lines = read(b"10%\r100%\ndone\r\n", encoding_rs::UTF_8);
+        assert_eq!(texts(&lines), ["100%", "done"]);
+    }
+
+    #[test]
+    fn unfinished_line_is_shown_once_idle() {
+        let buffer = OutputBuffer::new(1);
+        let reader = Stalled(Some(b"done\nPassword: ".to_vec()));
+        let reading = buffer.read_from(Stream::Stdout, reader, encoding_rs::UTF_8);
+        let result = task::block_on(future::timeout(IDLE * 3, reading));
+        assert!(result.is_err(), "the reader never finishes");
+
+        let lines: Vec<Line> = buffer.pending.lock().unwrap().lines.clone().into();
+        assert_eq!(texts(&lines), ["done", "Password: "]);
+        assert!(!lines[0].partial);
+        assert!(lines[1].partial);
+    }
+
+    #[test]
+    fn oldest_pending_lines_are_dropped_and_counted() {
+        let buffer = OutputBuffer::new(1);
+        for i in 0..MAX_PENDING + 5 {
+            buffer.push(Stream::Stdout, i.to_string());
+        }
+        let pending = buffer.pending.lock().unwrap();
+        assert_eq!(pending.lines.len(), MAX_PENDING);
+        assert_eq!(pending.dropped, 5);
+        assert_eq!(pending.lines[0].text, "5");
+    }
+}
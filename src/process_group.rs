@@ -0,0 +1,417 @@
+//! Each hosted process is spawned as the leader of its own process group, so
+//! that stopping it also reaches whatever it started: the server behind
+//! `npm run dev`, the binary behind `cargo run`, or a shell's pipeline.
+//!
+//! A process can leave its group, with setsid or setpgid, and daemons do so
+//! on purpose. On Linux, `Descendants` follows those too, through the
+//! parent links in /proc.
+//!
+//! Outside unix these fall back to acting on the direct child only.
+
+use std::io;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "linux")]
+use std::sync::{Once, Weak};
+#[cfg(target_os = "linux")]
+use std::thread;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use crate::config::stop::StopSignal;
+
+/// Makes the spawned child the leader of a new process group.
+#[cfg(unix)]
+pub fn isolate(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+#[cfg(not(unix))]
+pub fn isolate(_cmd: &mut Command) {}
+
+/// Sends `signal` to every process in the group led by `pid`.
+#[cfg(unix)]
+pub fn signal(pid: u32, signal: StopSignal) -> io::Result<()> {
+    send(pid, signal.number())
+}
+
+#[cfg(not(unix))]
+pub fn signal(_pid: u32, signal: StopSignal) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is only supported on unix", signal.name()),
+    ))
+}
+
+/// SIGKILLs every process in the group led by `pid`.
+#[cfg(unix)]
+pub fn kill(pid: u32) -> io::Result<()> {
+    send(pid, libc::SIGKILL)
+}
+
+#[cfg(not(unix))]
+pub fn kill(_pid: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether anything is left in the group led by `pid`. The leader itself
+/// counts until it has been waited on.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_alive(pid: u32) -> bool {
+    match send(pid, 0) {
+        Ok(()) => true,
+        Err(e) => e.raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn send(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: kill has no memory safety requirements. The negative pid
+    // addresses the whole process group.
+    match unsafe { libc::kill(-(pid as libc::pid_t), signal) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Has the kernel SIGKILL the child if multi-host dies without stopping it,
+/// e.g. when it is killed itself. This only reaches the group leader, which is
+/// the best that can be done once multi-host is gone.
+///
+/// Strictly, the signal fires when the spawning *thread* exits. Processes
+/// are only started from the UI thread, which lives as long as multi-host.
+#[cfg(target_os = "linux")]
+pub fn die_with_parent(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    let parent = std::process::id() as libc::pid_t;
+    // SAFETY: only async-signal-safe calls are made between fork and exec
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // multi-host may have died before the prctl took effect
+            if libc::getppid() != parent {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn die_with_parent(_cmd: &mut Command) {}
+
+/// Everything a hosted process has started: its group, and whatever the
+/// group's members and their children started, wherever they moved
+/// themselves since. A process is remembered once seen, so its children are
+/// still found after the process between them has exited.
+#[derive(Debug)]
+pub struct Descendants {
+    // the group leader, which is the hosted process itself
+    group: u32,
+    #[cfg(target_os = "linux")]
+    known: Vec<Seen>,
+    // whether `known` has been filled in yet
+    #[cfg(target_os = "linux")]
+    looked: bool,
+}
+
+/// A process as it was when first seen. A pid can be reused once its process
+/// is gone; the start time tells the new process apart.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Seen {
+    pid: u32,
+    started: u64,
+}
+
+/// How often tracked processes are looked for.
+#[cfg(target_os = "linux")]
+const POLL: Duration = Duration::from_secs(1);
+
+/// Every `Descendants` still worth looking at, all refreshed from the same
+/// read of /proc, so the cost doesn't grow with the number of processes.
+#[cfg(target_os = "linux")]
+static TRACKED: Mutex<Vec<Weak<Mutex<Descendants>>>> = Mutex::new(Vec::new());
+
+#[cfg(target_os = "linux")]
+impl Descendants {
+    /// Starts following what the group led by `group` starts. They are
+    /// looked for every second, on a thread of their own, for as long as the
+    /// returned handle is kept and any of them are left.
+    pub fn track(group: u32) -> Arc<Mutex<Self>> {
+        static SCANNER: Once = Once::new();
+        SCANNER.call_once(|| {
+            thread::spawn(scan_tracked);
+        });
+        let descendants = Arc::new(Mutex::new(Self {
+            group,
+            known: vec![],
+            looked: false,
+        }));
+        TRACKED.lock().unwrap().push(Arc::downgrade(&descendants));
+        descendants
+    }
+
+    /// Looks for processes started since the last look, and forgets the
+    /// ones that have exited. This reads all of /proc.
+    pub fn refresh(&mut self) {
+        self.refresh_from(&linux::processes());
+    }
+
+    fn refresh_from(&mut self, processes: &[linux::Process]) {
+        self.looked = true;
+        self.known.retain(|seen| {
+            processes
+                .iter()
+                .any(|process| process.seen() == *seen && !process.zombie)
+        });
+        loop {
+            let found: Vec<Seen> = processes
+                .iter()
+                .filter(|process| !process.zombie)
+                .filter(|process| !self.known.iter().any(|seen| seen.pid == process.pid))
+                .filter(|process| {
+                    process.group == self.group
+                        || self.known.iter().any(|seen| seen.pid == process.parent)
+                })
+                .map(|process| process.seen())
+                .collect();
+            if found.is_empty() {
+                break;
+            }
+            self.known.extend(found);
+        }
+    }
+
+    /// Whether any of them were still running at the last look.
+    pub fn any_alive(&self) -> bool {
+        !self.known.is_empty()
+    }
+
+    // With nothing left, nothing can start anything new
+    fn finished(&self) -> bool {
+        self.looked && self.known.is_empty()
+    }
+
+    /// SIGKILLs the group and everything else known to descend from it,
+    /// returning whether any of them were still there.
+    pub fn kill(&mut self) -> bool {
+        let mut killed = kill(self.group).is_ok();
+        for seen in &self.known {
+            // a pid can have been reused since the last look, which its
+            // start time gives away
+            if linux::process(seen.pid).is_some_and(|process| process.seen() == *seen) {
+                // SAFETY: kill has no memory safety requirements
+                killed |= unsafe { libc::kill(seen.pid as libc::pid_t, libc::SIGKILL) } == 0;
+            }
+        }
+        killed
+    }
+}
+
+/// Refreshes everything tracked every `POLL`, dropping what is finished or
+/// no longer wanted.
+#[cfg(target_os = "linux")]
+fn scan_tracked() {
+    loop {
+        thread::sleep(POLL);
+        let tracked: Vec<Arc<Mutex<Descendants>>> = {
+            let mut tracked = TRACKED.lock().unwrap();
+            tracked.retain(|descendants| {
+                descendants
+                    .upgrade()
+                    .is_some_and(|descendants| !descendants.lock().unwrap().finished())
+            });
+            tracked.iter().filter_map(Weak::upgrade).collect()
+        };
+        if tracked.is_empty() {
+            continue;
+        }
+        let processes = linux::processes();
+        for descendants in tracked {
+            descendants.lock().unwrap().refresh_from(&processes);
+        }
+    }
+}
+
+/// Without /proc only the group can be followed.
+#[cfg(not(target_os = "linux"))]
+impl Descendants {
+    pub fn track(group: u32) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self { group }))
+    }
+
+    pub fn refresh(&mut self) {}
+
+    pub fn any_alive(&self) -> bool {
+        is_alive(self.group)
+    }
+
+    pub fn kill(&mut self) -> bool {
+        is_alive(self.group) && kill(self.group).is_ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::Seen;
+
+    /// The parts of /proc/<pid>/stat that `Descendants` needs.
+    pub struct Process {
+        pub pid: u32,
+        pub parent: u32,
+        pub group: u32,
+        pub started: u64,
+        // exited but not yet waited on by its parent
+        pub zombie: bool,
+    }
+
+    impl Process {
+        pub fn seen(&self) -> Seen {
+            Seen {
+                pid: self.pid,
+                started: self.started,
+            }
+        }
+    }
+
+    /// The process with `pid`, if it is still there.
+    pub fn process(pid: u32) -> Option<Process> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        parse_stat(pid, &stat)
+    }
+
+    /// Every process that can be seen. Ones that exit while this reads are
+    /// left out.
+    pub fn processes() -> Vec<Process> {
+        let Ok(entries) = std::fs::read_dir("/proc") else {
+            return vec![];
+        };
+        entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter_map(process)
+            .collect()
+    }
+
+    /// The command name comes second, in parentheses, and can itself contain
+    /// spaces and parentheses, so the fields are counted from the last `)`.
+    pub fn parse_stat(pid: u32, stat: &str) -> Option<Process> {
+        let (_, fields) = stat.rsplit_once(')')?;
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        Some(Process {
+            pid,
+            zombie: *fields.first()? == "Z",
+            parent: fields.get(1)?.parse().ok()?,
+            group: fields.get(2)?.parse().ok()?,
+            // field 22 of the file, with the first two not in `fields`
+            started: fields.get(19)?.parse().ok()?,
+        })
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    // /proc/<pid>/stat, with everything after the start time cut off
+    fn stat(pid: u32, comm: &str, state: &str, parent: u32, group: u32, started: u64) -> String {
+        format!(
+            "{} ({}) {} {} {} {} 0 -1 4194560 100 0 0 0 0 0 0 0 20 0 1 0 {} 1000",
+            pid, comm, state, parent, group, group, started
+        )
+    }
+
+    fn process(pid: u32, parent: u32, group: u32) -> linux::Process {
+        linux::parse_stat(pid, &stat(pid, "sh", "S", parent, group, pid as u64)).unwrap()
+    }
+
+    #[test]
+    fn stat_fields() {
+        let process = linux::parse_stat(1234, &stat(1234, "node", "S", 1, 1200, 98765)).unwrap();
+        assert_eq!(process.pid, 1234);
+        assert_eq!(process.parent, 1);
+        assert_eq!(process.group, 1200);
+        assert_eq!(process.started, 98765);
+        assert!(!process.zombie);
+    }
+
+    #[test]
+    fn command_names_with_spaces_and_parentheses() {
+        for comm in ["tmux: server", "(sd-pam)", "a) S 9 9 9 (b", "x)) Z 1 2 ("] {
+            let process = linux::parse_stat(7, &stat(7, comm, "R", 3, 5, 11)).unwrap();
+            assert_eq!(
+                (process.parent, process.group, process.started),
+                (3, 5, 11),
+                "{}",
+                comm
+            );
+            assert!(!process.zombie, "{}", comm);
+        }
+    }
+
+    #[test]
+    fn zombies() {
+        let process = linux::parse_stat(7, &stat(7, "web", "Z", 3, 5, 11)).unwrap();
+        assert!(process.zombie);
+    }
+
+    #[test]
+    fn malformed_stat() {
+        assert!(linux::parse_stat(7, "").is_none());
+        assert!(linux::parse_stat(7, "7 (web S 3 5 5").is_none());
+        assert!(linux::parse_stat(7, "7 (web) S 3 5 5 0").is_none());
+        assert!(
+            linux::parse_stat(7, &stat(7, "web", "S", 3, 5, 11).replace(" 3 5 ", " x 5 "))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn follows_children_that_leave_the_group() {
+        let mut descendants = Descendants {
+            group: 100,
+            known: vec![],
+            looked: false,
+        };
+        assert!(!descendants.finished());
+
+        // 102 moved itself to a group of its own, and started 103
+        let processes = vec![
+            process(100, 1, 100),
+            process(101, 100, 100),
+            process(102, 101, 102),
+            process(103, 102, 102),
+            process(200, 1, 200),
+        ];
+        descendants.refresh_from(&processes);
+        let mut pids: Vec<u32> = descendants.known.iter().map(|seen| seen.pid).collect();
+        pids.sort();
+        assert_eq!(pids, [100, 101, 102, 103]);
+
+        // 103 is still found with 101 and 102 gone
+        descendants.refresh_from(&[process(103, 1, 102), process(200, 1, 200)]);
+        assert_eq!(
+            descendants.known,
+            [Seen {
+                pid: 103,
+                started: 103
+            }]
+        );
+
+        // a reused pid is someone else's process
+        let mut reused = process(103, 1, 300);
+        reused.started = 5000;
+        descendants.refresh_from(&[reused]);
+        assert!(!descendants.any_alive());
+        assert!(descendants.finished());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// Where a hosted process is in its life. Every state but `NotRun` records
+/// when it was entered, and the states with a child carry its pid.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessStatus {
+    NotRun,
+    /// Queued until the processes it depends on are ready.
+    Waiting {
+        since: Instant,
+    },
+    /// Spawned, but its readiness check hasn't passed yet.
+    Starting {
+        pid: u32,
+        since: Instant,
+    },
+    Running {
+        pid: u32,
+        since: Instant,
+    },
+    /// Asked to exit, and given `stop_timeout` to do so.
+    Stopping {
+        pid: u32,
+        since: Instant,
+    },
+    /// Exited with a success code, or with any code after being stopped.
+    Exited {
+        code: i32,
+        at: Instant,
+    },
+    Killed {
+        signal: i32,
+        at: Instant,
+    },
+    /// Exited on its own with a code the restart policy counts as failure.
+    Crashed {
+        code: i32,
+        at: Instant,
+    },
+    FailedToStart {
+        reason: String,
+        at: Instant,
+    },
+    /// Exited and waiting out its backoff before restart number `attempt`.
+    Restarting {
+        attempt: u32,
+        since: Instant,
+    },
+    /// Kept crashing, so restarts were given up on.
+    CrashLooping {
+        since: Instant,
+    },
+}
+
+impl ProcessStatus {
+    /// What a child's exit means, given whether it had been asked to stop
+    /// and whether its exit code counts as a success.
+    pub fn after_exit(status: ExitStatus, at: Instant, stopping: bool, success: bool) -> Self {
+        match (status.code(), exit_signal(status)) {
+            (Some(code), _) if stopping || success => ProcessStatus::Exited { code, at },
+            (Some(code), _) => ProcessStatus::Crashed { code, at },
+            (None, Some(signal)) => ProcessStatus::Killed { signal, at },
+            (None, None) => ProcessStatus::Crashed { code: -1, at },
+        }
+    }
+
+    /// The pid of the child, while there is one.
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            ProcessStatus::Starting { pid, .. }
+            | ProcessStatus::Running { pid, .. }
+            | ProcessStatus::Stopping { pid, .. } => Some(*pid),
+            _ => None,
+        }
+    }
+
+    /// When this state was entered.
+    pub fn since(&self) -> Option<Instant> {
+        match self {
+            ProcessStatus::NotRun => None,
+            ProcessStatus::Waiting { since }
+            | ProcessStatus::Starting { since, .. }
+            | ProcessStatus::Running { since, .. }
+            | ProcessStatus::Stopping { since, .. }
+            | ProcessStatus::Restarting { since, .. }
+            | ProcessStatus::CrashLooping { since } => Some(*since),
+            ProcessStatus::Exited { at, .. }
+            | ProcessStatus::Killed { at, .. }
+            | ProcessStatus::Crashed { at, .. }
+            | ProcessStatus::FailedToStart { at, .. } => Some(*at),
+        }
+    }
+
+    /// Whether the process is over and can be started again.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            ProcessStatus::NotRun
+                | ProcessStatus::Exited { .. }
+                | ProcessStatus::Killed { .. }
+                | ProcessStatus::Crashed { .. }
+                | ProcessStatus::FailedToStart { .. }
+                | ProcessStatus::CrashLooping { .. }
+        )
+    }
+
+    /// A few words for the process list. Running processes don't need one.
+    pub fn short(&self) -> Option<String> {
+        match self {
+            ProcessStatus::NotRun | ProcessStatus::Running { .. } => None,
+            ProcessStatus::Waiting { .. } => Some("waiting".to_string()),
+            ProcessStatus::Starting { .. } => Some("starting".to_string()),
+            ProcessStatus::Stopping { .. } => Some("stopping".to_string()),
+            ProcessStatus::Exited { code, .. } => Some(format!("exited {}", code)),
+            ProcessStatus::Killed { signal, .. } => Some(signal_name(*signal)),
+            ProcessStatus::Crashed { code, .. } => Some(format!("crashed {}", code)),
+            ProcessStatus::FailedToStart { .. } => Some("failed to start".to_string()),
+            ProcessStatus::Restarting { .. } => Some("restarting".to_string()),
+            ProcessStatus::CrashLooping { .. } => Some("crash looping".to_string()),
+        }
+    }
+
+    /// The status along with how long it has been that way, e.g.
+    /// `running, pid 4242, for 5m 3s`.
+    pub fn describe(&self) -> String {
+        match self.since() {
+            None => self.to_string(),
+            Some(since) if self.is_finished() => {
+                format!("{}, {} ago", self, format_elapsed(since.elapsed()))
+            }
+            Some(since) => format!("{}, for {}", self, format_elapsed(since.elapsed())),
+        }
+    }
+}
+
+impl fmt::Display for ProcessStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessStatus::NotRun => write!(f, "not run"),
+            ProcessStatus::Waiting { .. } => write!(f, "waiting for dependencies"),
+            ProcessStatus::Starting { pid, .. } => write!(f, "starting, pid {}", pid),
+            ProcessStatus::Running { pid, .. } => write!(f, "running, pid {}", pid),
+            ProcessStatus::Stopping { pid, .. } => write!(f, "stopping, pid {}", pid),
+            ProcessStatus::Exited { code, .. } => write!(f, "exited with code {}", code),
+            ProcessStatus::Killed { signal, .. } => {
+                write!(f, "killed by {}", signal_name(*signal))
+            }
+            ProcessStatus::Crashed { code, .. } => write!(f, "crashed with code {}", code),
+            ProcessStatus::FailedToStart { reason, .. } => {
+                write!(f, "failed to start: {}", reason)
+            }
+            ProcessStatus::Restarting { attempt, .. } => {
+                write!(f, "restarting, attempt {}", attempt)
+            }
+            ProcessStatus::CrashLooping { .. } => write!(f, "crash looping"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        6 => "SIGABRT",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        15 => "SIGTERM",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn code(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn signal(signal: i32) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    #[test]
+    fn successful_exit() {
+        let at = Instant::now();
+        assert_eq!(
+            ProcessStatus::after_exit(code(0), at, false, true),
+            ProcessStatus::Exited { code: 0, at }
+        );
+        // success codes come from the restart policy, so needn't be 0
+        assert_eq!(
+            ProcessStatus::after_exit(code(3), at, false, true),
+            ProcessStatus::Exited { code: 3, at }
+        );
+    }
+
+    #[test]
+    fn failed_exit_is_a_crash() {
+        let at = Instant::now();
+        assert_eq!(
+            ProcessStatus::after_exit(code(1), at, false, false),
+            ProcessStatus::Crashed { code: 1, at }
+        );
+    }
+
+    #[test]
+    fn any_code_is_an_exit_once_stopping() {
+        let at = Instant::now();
+        assert_eq!(
+            ProcessStatus::after_exit(code(143), at, true, false),
+            ProcessStatus::Exited { code: 143, at }
+        );
+    }
+
+    #[test]
+    fn killed_by_a_signal() {
+        let at = Instant::now();
+        assert_eq!(
+            ProcessStatus::after_exit(signal(9), at, false, false),
+            ProcessStatus::Killed { signal: 9, at }
+        );
+        assert_eq!(
+            ProcessStatus::after_exit(signal(15), at, true, false),
+            ProcessStatus::Killed { signal: 15, at }
+        );
+        assert_eq!(
+            ProcessStatus::after_exit(signal(15), at, true, false).short(),
+            Some("SIGTERM".to_string())
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Pseudo-terminals for `tty:` processes. Plenty of tools turn off colour,
+//! progress bars or line buffering when their output is a pipe; under a
+//! pseudo-terminal they behave as they would in a terminal window. The
+//! terminal's output is read the same way a pipe's is.
+//!
+//! Outside unix there are no pseudo-terminals, and opening one fails.
+
+use std::io;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::task::{Context, Poll};
+
+use async_std::io::{Read, Write};
+
+use crate::config::tty::WindowSize;
+
+/// The multi-host end of a pseudo-terminal.
+#[derive(Debug)]
+pub struct Pty {
+    #[cfg(unix)]
+    master: std::fs::File,
+    #[cfg(not(unix))]
+    master: std::convert::Infallible,
+}
+
+/// Reads what the process writes to its terminal.
+pub struct PtyReader {
+    #[cfg(unix)]
+    master: async_io::Async<std::fs::File>,
+    #[cfg(not(unix))]
+    master: std::convert::Infallible,
+}
+
+/// Types into the process's terminal.
+pub struct PtyWriter {
+    #[cfg(unix)]
+    master: async_io::Async<std::fs::File>,
+    #[cfg(not(unix))]
+    master: std::convert::Infallible,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Opens a pseudo-terminal and makes it `cmd`'s stdin, stdout, stderr
+    /// and controlling terminal. The child gets a session of its own, which
+    /// also makes it the leader of a process group like `process_group`'s.
+    pub fn open(cmd: &mut Command, size: WindowSize) -> io::Result<(Pty, [Stdio; 3])> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::os::unix::process::CommandExt;
+
+        let mut master = -1;
+        let mut slave = -1;
+        let size = window_size(size);
+        // SAFETY: openpty writes the two descriptors it opens, and only reads
+        // the window size; the name and termios are left out.
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty succeeded, so both are open and now owned here
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        set_close_on_exec(&master)?;
+        set_close_on_exec(&slave)?;
+
+        // SAFETY: only async-signal-safe calls are made between fork and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // stdin is the terminal by the time this runs
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let stdio = [
+            Stdio::from(slave.try_clone()?),
+            Stdio::from(slave.try_clone()?),
+            Stdio::from(slave),
+        ];
+        let pty = Pty {
+            master: master.into(),
+        };
+        Ok((pty, stdio))
+    }
+
+    pub fn reader(&self) -> io::Result<PtyReader> {
+        let master = async_io::Async::new(self.master.try_clone()?)?;
+        Ok(PtyReader { master })
+    }
+
+    pub fn writer(&self) -> io::Result<PtyWriter> {
+        let master = async_io::Async::new(self.master.try_clone()?)?;
+        Ok(PtyWriter { master })
+    }
+
+    /// Changes the size of the terminal, which sends the process SIGWINCH.
+    pub fn resize(&self, size: WindowSize) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let size = window_size(size);
+        // SAFETY: TIOCSWINSZ only reads the winsize it is given
+        match unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Pty {
+    pub fn open(_cmd: &mut Command, _size: WindowSize) -> io::Result<(Pty, [Stdio; 3])> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "`tty` is only supported on unix",
+        ))
+    }
+
+    pub fn reader(&self) -> io::Result<PtyReader> {
+        match self.master {}
+    }
+
+    pub fn writer(&self) -> io::Result<PtyWriter> {
+        match self.master {}
+    }
+
+    pub fn resize(&self, _size: WindowSize) -> io::Result<()> {
+        match self.master {}
+    }
+}
+
+#[cfg(unix)]
+fn window_size(size: WindowSize) -> libc::winsize {
+    libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// So that other children don't hold the terminal open after this one exits.
+#[cfg(unix)]
+fn set_close_on_exec(fd: &std::os::fd::OwnedFd) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: fcntl has no memory safety requirements
+    match unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+impl Read for PtyReader {
+    #[cfg(unix)]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.master).poll_read(cx, buf) {
+            // Linux reports the other end closing, once the process and
+            // everything it started have exited, as an error rather than EOF
+            Poll::Ready(Err(e)) if e.raw_os_error() == Some(libc::EIO) => Poll::Ready(Ok(0)),
+            poll => poll,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.master {}
+    }
+}
+
+#[cfg(unix)]
+impl Write for PtyWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.master).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.master).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.master).poll_close(cx)
+    }
+}
+
+#[cfg(not(unix))]
+impl Write for PtyWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.master {}
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.master {}
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.master {}
+    }
+}
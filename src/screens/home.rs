@@ -1,65 +1,707 @@
-use crate::ProcessDefinition;
-use crate::hosted_process::ProcessStatus;
-use crate::{Message, Screen, hosted_process::HostedProcess};
+use crate::config::ProcessDefinition;
+use crate::config::dependencies;
+use crate::config::diff::ConfigDiff;
+use crate::config::scrollback::ScrollbackLimits;
+use crate::config::tty::WindowSize;
+use crate::hosted_process::{HostedProcess, ProcessId, RunId};
+use crate::input::{HistoryStep, Input};
+use crate::process_status::ProcessStatus;
+use crate::screens::log_view::{self, LogViewport};
+use crate::screens::measured::measured;
+use crate::scrollback::{Stream, StreamFilter};
+use crate::{Message, Screen};
+use async_std::task;
 use iced::Length::{Fill, FillPortion};
+use iced::advanced::widget::{self, operation::focusable};
 use iced::futures::channel::mpsc::Sender;
-use iced::widget::{button, column, container, row, scrollable, text};
-use iced::{Element, Subscription, Task};
+use iced::keyboard::{self, Key, key::Named};
+use iced::widget::{
+    button, column, container, horizontal_space, row, scrollable, text, text_input,
+};
+use iced::{Element, Size, Subscription, Task};
 use std::fmt::Write;
+use std::process::ExitStatus;
 use std::rc::Rc;
+use std::time::Instant;
 
 #[derive(Debug)]
 pub struct HomeScreen {
+    // in the order they're listed, which is the order of the config
     pub hosted_processes: Vec<HostedProcess>,
-    pub focused_process: usize,
+    pub focused_process: Option<ProcessId>,
+    log_viewport: LogViewport,
+    stream_filter: StreamFilter,
+    // how many characters fit in the output pane, as `tty` processes see
+    // it, once it has been laid out
+    pane_size: Option<WindowSize>,
+    // auto-start waits for the pane size, so `tty` processes start at it
+    pending_auto_start: Option<Sender<Message>>,
+    // the input line under the output, and which line of the focused
+    // process's history it was taken from. What was typed before going
+    // through the history is kept in `draft`, to come back to after it.
+    input: String,
+    history_position: Option<usize>,
+    draft: String,
+    // handed out to processes as they're added, never reused
+    next_process_id: ProcessId,
+    // how much output all the processes together may keep
+    scrollback_budget: ScrollbackLimits,
     show_side_bar: bool,
+    reload_preview: Option<ConfigDiff>,
+    reload_error: Option<String>,
+    // a process the user asked to start while its dependencies are stopped
+    dependency_prompt: Option<ProcessId>,
+    // running processes to stop once everything that depends on them has
+    // exited, dependents first
+    stop_queue: Vec<ProcessId>,
+    quit: Option<Quit>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Quit {
+    // asking whether to stop the running processes and quit
+    Confirming,
+    // stopping everything, quitting once it has all exited
+    Stopping,
 }
 
 impl HomeScreen {
-    pub fn new(processes: Rc<Vec<ProcessDefinition>>) -> Self {
-        Self {
-            hosted_processes: processes
-                .iter()
-                .map(|process_definition| HostedProcess::new(process_definition.clone()))
-                .collect(),
-            focused_process: 0,
+    pub fn new(processes: Rc<Vec<ProcessDefinition>>, scrollback_budget: ScrollbackLimits) -> Self {
+        let mut home_screen = Self {
+            hosted_processes: vec![],
+            focused_process: None,
+            log_viewport: LogViewport::default(),
+            stream_filter: StreamFilter::default(),
+            pane_size: None,
+            pending_auto_start: None,
+            input: String::new(),
+            history_position: None,
+            draft: String::new(),
+            next_process_id: ProcessId::default(),
+            scrollback_budget,
             show_side_bar: true,
+            reload_preview: None,
+            reload_error: None,
+            dependency_prompt: None,
+            stop_queue: vec![],
+            quit: None,
+        };
+        for process_definition in processes.iter() {
+            home_screen.add(process_definition.clone());
         }
+        home_screen.focused_process = home_screen.hosted_processes.first().map(|p| p.id);
+        home_screen
     }
 
-    pub fn auto_start(&mut self, sender: &Sender<Message>) -> Task<Message> {
-        let _ = self
-            .hosted_processes
+    fn add(&mut self, definition: ProcessDefinition) -> ProcessId {
+        let process_id = self.next_process_id;
+        self.next_process_id = process_id.next();
+        let mut process = HostedProcess::new(process_id, definition);
+        if let Some(pane_size) = self.pane_size {
+            process.pane_resized(pane_size);
+        }
+        self.hosted_processes.push(process);
+        process_id
+    }
+
+    fn get(&self, process_id: ProcessId) -> Option<&HostedProcess> {
+        self.hosted_processes
+            .iter()
+            .find(|process| process.id == process_id)
+    }
+
+    fn get_mut(&mut self, process_id: ProcessId) -> Option<&mut HostedProcess> {
+        self.hosted_processes
             .iter_mut()
-            .enumerate()
-            .for_each(|(process_id, process)| process.try_auto_start(process_id, sender.clone()));
+            .find(|process| process.id == process_id)
+    }
+
+    /// The process `run` belongs to, unless it has been started again since.
+    fn current_run_mut(&mut self, run: RunId) -> Option<&mut HostedProcess> {
+        self.get_mut(run.process_id)
+            .filter(|process| process.generation == run.generation)
+    }
+
+    /// Starts the auto-start processes, and anything they depend on, in
+    /// dependency order, once the output pane has been measured.
+    pub fn auto_start(&mut self, sender: &Sender<Message>) -> Task<Message> {
+        if self.pane_size.is_none() {
+            self.pending_auto_start = Some(sender.clone());
+            return Task::none();
+        }
+        let auto_start: Vec<ProcessId> = self
+            .processes()
+            .filter(|(_, process)| process.auto_start_enabled)
+            .map(|(process_id, _)| process_id)
+            .collect();
+        self.start_in_order(&auto_start, sender);
+        Task::none()
+    }
+
+    pub fn start_stop(&mut self, process_id: ProcessId, sender: &Sender<Message>) -> Task<Message> {
+        let Some(process) = self.get(process_id) else {
+            return Task::none();
+        };
+
+        match &process.status {
+            status if status.is_finished() => {
+                match self.unmet_dependencies(process_id).is_empty() {
+                    true => self.start_in_order(&[process_id], sender),
+                    false => self.dependency_prompt = Some(process_id),
+                }
+            }
+            ProcessStatus::Waiting { .. }
+            | ProcessStatus::Starting { .. }
+            | ProcessStatus::Running { .. }
+            | ProcessStatus::Restarting { .. } => {
+                if let Some(process) = self.get_mut(process_id) {
+                    process.stop();
+                }
+                self.fail_stranded();
+            }
+            _ => {
+                if let Some(process) = self.get_mut(process_id) {
+                    process.kill();
+                }
+            }
+        };
+
+        Task::none()
+    }
+
+    pub fn start_with_dependencies(
+        &mut self,
+        process_id: ProcessId,
+        sender: &Sender<Message>,
+    ) -> Task<Message> {
+        self.dependency_prompt = None;
+        let mut to_start = self.unmet_dependencies(process_id);
+        to_start.push(process_id);
+        self.start_in_order(&to_start, sender);
+        Task::none()
+    }
+
+    pub fn start_without_dependencies(
+        &mut self,
+        process_id: ProcessId,
+        sender: &Sender<Message>,
+    ) -> Task<Message> {
+        self.dependency_prompt = None;
+        if let Some(process) = self.get_mut(process_id) {
+            process.reset_restarts();
+            start_process(process, sender);
+        }
+        Task::none()
+    }
+
+    pub fn dismiss_dependency_prompt(&mut self) -> Task<Message> {
+        self.dependency_prompt = None;
+        Task::none()
+    }
+
+    /// Stops every running process, dependents before their dependencies:
+    /// a process is only asked to stop once all its dependents have exited.
+    pub fn stop_all(&mut self) -> Task<Message> {
+        self.stop_queue.clear();
+        for process_id in self.start_order().into_iter().rev() {
+            if let Some(process) = self.get_mut(process_id)
+                && process.is_active()
+            {
+                process.restart_after_stop = false;
+                self.stop_queue.push(process_id);
+            }
+        }
+        self.stop_queued();
+        Task::none()
+    }
+
+    /// Stops the queued processes that nothing running depends on anymore.
+    /// Ones that exited on their own, or were stopped by hand, leave the queue.
+    fn stop_queued(&mut self) {
+        for process_id in std::mem::take(&mut self.stop_queue) {
+            let Some(process) = self.get(process_id) else {
+                continue;
+            };
+            if !process.is_active() || matches!(process.status, ProcessStatus::Stopping { .. }) {
+                continue;
+            }
+            match self.has_live_dependents(process_id) {
+                true => self.stop_queue.push(process_id),
+                false => {
+                    if let Some(process) = self.get_mut(process_id) {
+                        process.stop();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether any process that depends on `process_id` hasn't exited yet.
+    fn has_live_dependents(&self, process_id: ProcessId) -> bool {
+        let Some(name) = self.get(process_id).map(|process| process.name.as_str()) else {
+            return false;
+        };
+        self.processes().any(|(_, process)| {
+            process
+                .depends_on
+                .iter()
+                .any(|dependency| dependency == name)
+                && (process.is_alive() || matches!(process.status, ProcessStatus::Stopping { .. }))
+        })
+    }
+
+    /// Whether anything is still running, including processes that have
+    /// been asked to stop but haven't exited yet.
+    pub fn has_live_processes(&self) -> bool {
+        self.processes().any(|(_, process)| {
+            process.is_alive() || matches!(process.status, ProcessStatus::Stopping { .. })
+        })
+    }
+
+    pub fn confirm_quit(&mut self) -> Task<Message> {
+        self.quit = Some(Quit::Confirming);
+        Task::none()
+    }
+
+    pub fn cancel_quit(&mut self) -> Task<Message> {
+        self.quit = None;
+        Task::none()
+    }
+
+    /// Stops everything with its stop policy; multi-host quits once
+    /// `has_live_processes` goes false.
+    pub fn begin_quit(&mut self) -> Task<Message> {
+        self.quit = Some(Quit::Stopping);
+        self.stop_all()
+    }
+
+    pub fn is_quitting(&self) -> bool {
+        self.quit == Some(Quit::Stopping)
+    }
+
+    /// Skips the stop timeouts, for when the user won't wait for them.
+    pub fn kill_all(&mut self) {
+        self.stop_queue.clear();
+        for process in self.hosted_processes.iter_mut() {
+            if process.is_alive() || matches!(process.status, ProcessStatus::Stopping { .. }) {
+                process.kill();
+            }
+        }
+    }
+
+    /// A process passed its readiness check, so anything waiting on it may
+    /// be able to start now.
+    pub fn process_ready(&mut self, run: RunId, sender: &Sender<Message>) -> Task<Message> {
+        let Some(process) = self.current_run_mut(run) else {
+            return Task::none();
+        };
+        if !matches!(process.status, ProcessStatus::Starting { .. }) {
+            return Task::none();
+        }
+        process.ready();
+        writeln!(process.output, "process is ready").expect("appending output failed");
+
+        let waiting: Vec<ProcessId> = self
+            .processes()
+            .filter(|(_, process)| matches!(process.status, ProcessStatus::Waiting { .. }))
+            .map(|(process_id, _)| process_id)
+            .collect();
+        self.start_when_ready(&waiting, sender);
         Task::none()
     }
 
-    pub fn start_stop(&mut self, process_id: usize, sender: &Sender<Message>) -> Task<Message> {
-        let process = &mut self.hosted_processes[process_id];
+    /// A child exited. Unless the user stopped it, the restart policy decides
+    /// whether it comes back, after a growing backoff. Its exit may also let
+    /// a queued stop of one of its dependencies go ahead.
+    pub fn process_exited(
+        &mut self,
+        run: RunId,
+        status: ExitStatus,
+        at: Instant,
+        sender: &Sender<Message>,
+    ) -> Task<Message> {
+        let task = self.handle_exit(run, status, at, sender);
+        self.fail_stranded();
+        self.stop_queued();
+        task
+    }
+
+    fn handle_exit(
+        &mut self,
+        run: RunId,
+        status: ExitStatus,
+        at: Instant,
+        sender: &Sender<Message>,
+    ) -> Task<Message> {
+        let Some(process) = self.get_mut(run.process_id) else {
+            return Task::none();
+        };
+        // an earlier run of the process, already dealt with
+        if process.generation != run.generation {
+            writeln!(
+                process.output,
+                "[run {}] process exited ({})",
+                run.generation, status
+            )
+            .expect("appending output failed");
+            return Task::none();
+        }
+        if process.status.pid().is_none() {
+            return Task::none();
+        }
+        writeln!(process.output, "process exited ({})", status).expect("appending output failed");
+        if matches!(process.status, ProcessStatus::Stopping { .. }) {
+            process.exited(status, at);
+            if process.restart_after_stop {
+                start_process(process, sender);
+            }
+            return Task::none();
+        }
+        process.warn_about_leftovers();
 
-        match process.status {
-            ProcessStatus::NotRun | ProcessStatus::Stopped => {
-                match process.start(process_id, sender.clone()) {
-                    Ok(_) => process.run(),
-                    Err(_) => writeln!(process.output, "error starting process").unwrap(),
+        match process.exited(status, at) {
+            Some(delay) => {
+                writeln!(
+                    process.output,
+                    "restarting in {:?} (attempt {} of {})",
+                    delay,
+                    process.restart_attempts(),
+                    process.restart.max_retries
+                )
+                .expect("appending output failed");
+                Task::perform(task::sleep(delay), move |_| Message::RestartProcess(run))
+            }
+            None => {
+                if let ProcessStatus::CrashLooping { .. } = process.status {
+                    writeln!(
+                        process.output,
+                        "giving up after {} restarts in a row",
+                        process.restart_attempts()
+                    )
+                    .expect("appending output failed");
                 }
+                Task::none()
+            }
+        }
+    }
+
+    /// Nothing can tell when a child whose exit couldn't be waited for is
+    /// gone, so it is killed rather than left running unwatched.
+    pub fn process_lost(&mut self, run: RunId, error: String, at: Instant) -> Task<Message> {
+        if let Some(process) = self.current_run_mut(run) {
+            writeln!(process.output, "couldn't wait for process: {}", error)
+                .expect("appending output failed");
+            process.kill();
+            process.lost(at);
+        }
+        // as with an exit, nothing waiting on it is left waiting
+        self.fail_stranded();
+        self.stop_queued();
+        Task::none()
+    }
+
+    pub fn restart_process(&mut self, run: RunId, sender: &Sender<Message>) -> Task<Message> {
+        // The user may have cancelled the restart during the backoff, or
+        // started the process themselves
+        if let Some(process) = self.current_run_mut(run)
+            && let ProcessStatus::Restarting { .. } = process.status
+        {
+            start_process(process, sender);
+        }
+        self.fail_stranded();
+        Task::none()
+    }
+
+    /// Starts `process_ids` and everything they depend on that isn't running
+    /// yet, dependencies first.
+    fn start_in_order(&mut self, process_ids: &[ProcessId], sender: &Sender<Message>) {
+        let mut wanted = process_ids.to_vec();
+        for &process_id in process_ids {
+            wanted.extend(self.unmet_dependencies(process_id));
+        }
+        self.start_when_ready(&wanted, sender);
+    }
+
+    /// Starts each of `process_ids` whose dependencies are all ready, and
+    /// queues the rest as waiting. Going in start order means a dependency
+    /// without a readiness check is running before its dependents are looked at.
+    fn start_when_ready(&mut self, process_ids: &[ProcessId], sender: &Sender<Message>) {
+        for process_id in self.start_order() {
+            if !process_ids.contains(&process_id) {
+                continue;
+            }
+            let dependencies_ready = self.dependencies_ready(process_id);
+            let Some(process) = self.get_mut(process_id) else {
+                continue;
+            };
+            if process.is_active() {
+                continue;
+            }
+            process.reset_restarts();
+            match dependencies_ready {
+                true => start_process(process, sender),
+                false => process.wait(),
+            }
+        }
+        self.fail_stranded();
+    }
+
+    /// Waiting processes only start once their dependencies are ready, so
+    /// one waiting on a dependency that finished without getting there
+    /// would wait forever. Those fail instead, and so does anything waiting
+    /// on them in turn.
+    fn fail_stranded(&mut self) {
+        for process_id in self.start_order() {
+            let Some(process) = self.get(process_id) else {
+                continue;
+            };
+            if !matches!(process.status, ProcessStatus::Waiting { .. }) {
+                continue;
             }
-            ProcessStatus::Running => {
-                process.stop();
+            let finished = process
+                .depends_on
+                .iter()
+                .filter_map(|name| self.id_of(name))
+                .filter_map(|dependency| self.get(dependency))
+                .find(|dependency| dependency.status.is_finished());
+            let Some(dependency) = finished else {
+                continue;
+            };
+            let reason = format!(
+                "dependency {} never became ready ({})",
+                dependency.name, dependency.status
+            );
+            if let Some(process) = self.get_mut(process_id) {
+                writeln!(process.output, "error starting process: {}", reason)
+                    .expect("appending output failed");
+                process.failed_to_start(reason);
+            }
+        }
+    }
+
+    fn dependencies_ready(&self, process_id: ProcessId) -> bool {
+        let Some(process) = self.get(process_id) else {
+            return false;
+        };
+        process
+            .depends_on
+            .iter()
+            .filter_map(|name| self.id_of(name))
+            .filter_map(|dependency| self.get(dependency))
+            .all(|dependency| matches!(dependency.status, ProcessStatus::Running { .. }))
+    }
+
+    fn processes(&self) -> impl Iterator<Item = (ProcessId, &HostedProcess)> {
+        self.hosted_processes
+            .iter()
+            .map(|process| (process.id, process))
+    }
+
+    /// Every live process id, ordered so dependencies come first. The config
+    /// is checked for cycles when it's loaded, so one here only happens if a
+    /// reload was half applied; list order is the best we can do then.
+    fn start_order(&self) -> Vec<ProcessId> {
+        let ids: Vec<ProcessId> = self.processes().map(|(process_id, _)| process_id).collect();
+        let depends_on: Vec<Vec<usize>> = self
+            .processes()
+            .map(|(_, process)| {
+                process
+                    .depends_on
+                    .iter()
+                    .filter_map(|name| self.id_of(name))
+                    .filter_map(|dependency| ids.iter().position(|&id| id == dependency))
+                    .collect()
+            })
+            .collect();
+
+        match dependencies::start_order(&depends_on) {
+            Ok(order) => order.into_iter().map(|i| ids[i]).collect(),
+            Err(_) => ids,
+        }
+    }
+
+    /// The processes `process_id` needs, directly or not, that haven't been
+    /// started, in start order.
+    fn unmet_dependencies(&self, process_id: ProcessId) -> Vec<ProcessId> {
+        let mut needed = vec![];
+        let mut pending = vec![process_id];
+        while let Some(current) = pending.pop() {
+            let Some(process) = self.get(current) else {
+                continue;
+            };
+            for dependency in process
+                .depends_on
+                .iter()
+                .filter_map(|name| self.id_of(name))
+            {
+                if !needed.contains(&dependency) {
+                    needed.push(dependency);
+                    pending.push(dependency);
+                }
             }
+        }
+
+        self.start_order()
+            .into_iter()
+            .filter(|process_id| needed.contains(process_id))
+            .filter(|&process_id| {
+                self.get(process_id)
+                    .is_some_and(|process| process.status.is_finished())
+            })
+            .collect()
+    }
+
+    pub fn show_reload_preview(&mut self, diff: ConfigDiff) -> Task<Message> {
+        self.reload_error = None;
+        self.reload_preview = Some(diff);
+        Task::none()
+    }
+
+    pub fn show_reload_error(&mut self, error: String) -> Task<Message> {
+        self.reload_preview = None;
+        self.reload_error = Some(error);
+        Task::none()
+    }
+
+    pub fn dismiss_reload(&mut self) -> Task<Message> {
+        self.reload_preview = None;
+        self.reload_error = None;
+        Task::none()
+    }
+
+    /// Applies the previewed config reload. Processes that weren't touched
+    /// by the reload keep running.
+    pub fn apply_reload(
+        &mut self,
+        restart_changed: bool,
+        sender: Option<&Sender<Message>>,
+    ) -> Task<Message> {
+        let Some(diff) = self.reload_preview.take() else {
+            return Task::none();
         };
 
+        for name in &diff.removed {
+            let Some(process_id) = self.id_of(name) else {
+                continue;
+            };
+            self.hosted_processes.retain_mut(|process| {
+                if process.id != process_id {
+                    return true;
+                }
+                if process.is_alive() {
+                    process.stop();
+                }
+                false
+            });
+        }
+
+        for changed in diff.changed {
+            let Some(process_id) = self.id_of(&changed.definition.name) else {
+                continue;
+            };
+            let Some(process) = self.get_mut(process_id) else {
+                continue;
+            };
+
+            let restart = restart_changed && changed.needs_restart() && process.is_alive();
+            process.redefine(changed.definition);
+            if restart {
+                process.restart();
+            }
+        }
+
+        let mut added = vec![];
+        for definition in diff.added {
+            let auto_start = definition.auto_start;
+            let process_id = self.add(definition);
+            if auto_start {
+                added.push(process_id);
+            }
+        }
+        if let Some(sender) = sender {
+            self.start_in_order(&added, sender);
+        }
+
+        if self.focused().is_none() {
+            self.focused_process = self.hosted_processes.first().map(|p| p.id);
+        }
+
         Task::none()
     }
 
+    pub fn set_scrollback_budget(&mut self, budget: ScrollbackLimits) {
+        self.scrollback_budget = budget;
+        self.trim_scrollback();
+    }
+
+    /// Drops the oldest output of whichever process has the most, until all
+    /// of it fits in the budget.
+    fn trim_scrollback(&mut self) {
+        let budget = self.scrollback_budget;
+        let mut lines: usize = self.processes().map(|(_, p)| p.output.len()).sum();
+        let mut bytes: usize = self.processes().map(|(_, p)| p.output.bytes()).sum();
+        while lines > budget.lines || bytes > budget.bytes {
+            let largest =
+                self.hosted_processes
+                    .iter_mut()
+                    .max_by_key(|process| match bytes > budget.bytes {
+                        true => process.output.bytes(),
+                        false => process.output.len(),
+                    });
+            let Some(dropped) = largest.and_then(|process| process.output.drop_oldest()) else {
+                break;
+            };
+            lines -= 1;
+            bytes -= dropped;
+        }
+    }
+
+    fn id_of(&self, name: &str) -> Option<ProcessId> {
+        self.hosted_processes
+            .iter()
+            .find(|process| process.name == name)
+            .map(|process| process.id)
+    }
+
+    fn focused(&self) -> Option<&HostedProcess> {
+        self.get(self.focused_process?)
+    }
+
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::ProcessOutput(process_id, line) => {
-                writeln!(self.hosted_processes[process_id].output, "{}", line)
-                    .expect("appending output failed");
+            Message::ProcessOutput(run, lines) => {
+                // Output from a process removed by a config reload is dropped
+                let Some(process) = self.get_mut(run.process_id) else {
+                    return Task::none();
+                };
+                // and late output from an earlier run is labelled as such
+                if process.generation != run.generation {
+                    for line in lines {
+                        let prefix = format!("[run {}] ", run.generation);
+                        process.output.push(line.prefixed(&prefix));
+                    }
+                    return Task::none();
+                }
+                let ready = matches!(process.status, ProcessStatus::Starting { .. })
+                    && process.readiness.as_ref().is_some_and(|readiness| {
+                        lines.iter().any(|line| {
+                            line.stream != Stream::Note && readiness.check.matches_line(&line.text)
+                        })
+                    });
+                for line in lines {
+                    process.output.push(line);
+                }
+                self.trim_scrollback();
 
+                match ready {
+                    true => Task::done(Message::ProcessReady(run)),
+                    false => Task::none(),
+                }
+            }
+            Message::OutputDropped(run, lines) => {
+                if let Some(process) = self.get_mut(run.process_id) {
+                    process.output.count_dropped(lines);
+                }
                 Task::none()
             }
             _ => Task::none(),
@@ -67,36 +709,92 @@ impl HomeScreen {
     }
 
     pub fn subscription(&self) -> Vec<Subscription<Message>> {
-        vec![Subscription::run(
-            HostedProcess::subscribe_to_process_outputs,
-        )]
+        vec![
+            Subscription::run(HostedProcess::subscribe_to_process_outputs),
+            // the input line leaves the up and down arrows alone
+            keyboard::on_key_press(|key, _| match key {
+                Key::Named(Named::ArrowUp) => Some(Message::HistoryKeyPressed(HistoryStep::Older)),
+                Key::Named(Named::ArrowDown) => {
+                    Some(Message::HistoryKeyPressed(HistoryStep::Newer))
+                }
+                _ => None,
+            }),
+        ]
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let settings_button = button("Settings").on_press(Message::ChangeScreen(Screen::Settings));
         let sidebar_text = match self.show_side_bar {
             true => "<<",
             false => ">>",
         };
         let sidebar_toggle_button = button(sidebar_text).on_press(Message::ToggleHomeSideBar);
-        let top_pane = container(row!(sidebar_toggle_button, settings_button))
+        let stop_all_button = button("Stop all")
+            .style(button::danger)
+            .on_press(Message::StopAllProcesses);
+        let top_pane = container(row!(
+            sidebar_toggle_button,
+            settings_button,
+            stop_all_button
+        ))
+        .width(Fill)
+        .style(container::rounded_box)
+        .padding(10);
+
+        let output: Element<Message> = match self.focused() {
+            Some(process) => log_view::view(
+                &process.output,
+                self.stream_filter,
+                process.strip_color,
+                self.log_viewport,
+            ),
+            None => horizontal_space().into(),
+        };
+        let output = measured(
+            container(output).width(Fill).height(Fill),
+            Message::PaneResized,
+        );
+        let command_line = text(
+            self.focused()
+                .map(|process| format!("$ {}", process.launch.command_line()))
+                .unwrap_or_default(),
+        )
+        .font(iced::Font::MONOSPACE);
+        let status = text(
+            self.focused()
+                .map(|process| process.status.describe())
+                .unwrap_or_default(),
+        );
+        let stream_buttons = StreamFilter::ALL.map(|filter| {
+            let style = match filter == self.stream_filter {
+                true => button::primary,
+                false => button::secondary,
+            };
+            button(text(filter.to_string()))
+                .style(style)
+                .on_press(Message::ShowStreams(filter))
+                .into()
+        });
+        let stream_buttons = row(stream_buttons).spacing(2);
+        let right_pane = column![
+            container(
+                row![command_line, horizontal_space(), status, stream_buttons]
+                    .spacing(10)
+                    .align_y(iced::Alignment::Center)
+            )
             .width(Fill)
             .style(container::rounded_box)
-            .padding(10);
-
-        let right_pane_text = text(self.hosted_processes[self.focused_process].output.clone());
-        let right_pane = scrollable(container(right_pane_text).width(FillPortion(4)).padding(10))
-            .height(Fill)
-            .anchor_bottom();
+            .padding(5),
+            output,
+            self.input_line(),
+        ]
+        .width(FillPortion(4));
 
         // TODO: only do this work if sidebar is enabled
         let processes: Vec<iced::Element<Message>> = self
             .hosted_processes
             .iter()
-            .enumerate()
-            .map(|(process_id, process)| {
-                process.to_element(process_id, process_id == self.focused_process)
-            })
+            .map(|process| process.to_element(Some(process.id) == self.focused_process))
             .collect();
         let process_list = iced::widget::Column::with_children(processes);
         let left_pane = scrollable(
@@ -127,7 +825,17 @@ impl HomeScreen {
             })
             .padding(10);
 
-        let all_panes = column![top_pane, middle_pane, bottom_pane].spacing(3);
+        let mut all_panes = column![top_pane].spacing(3);
+        if let Some(reload_pane) = self.reload_pane() {
+            all_panes = all_panes.push(reload_pane);
+        }
+        if let Some(dependency_pane) = self.dependency_pane() {
+            all_panes = all_panes.push(dependency_pane);
+        }
+        if let Some(quit_pane) = self.quit_pane() {
+            all_panes = all_panes.push(quit_pane);
+        }
+        let all_panes = all_panes.push(middle_pane).push(bottom_pane);
 
         let main_window = container(all_panes)
             .center_x(Fill)
@@ -138,13 +846,402 @@ impl HomeScreen {
         container(main_window).into()
     }
 
-    pub fn focus(&mut self, process_id: usize) -> Task<Message> {
-        self.focused_process = process_id;
+    /// The banner shown while a config reload is waiting to be applied, or
+    /// when the reloaded config turned out to be invalid.
+    fn reload_pane(&self) -> Option<Element<'_, Message>> {
+        let dismiss_button = button("Dismiss")
+            .style(button::secondary)
+            .on_press(Message::DismissConfigReload);
+
+        if let Some(error) = &self.reload_error {
+            let content = column![
+                text("Config reload failed, still running the previous config:"),
+                text(error.as_str()).style(text::danger),
+                dismiss_button,
+            ]
+            .spacing(5);
+            return Some(
+                container(content)
+                    .width(Fill)
+                    .style(container::rounded_box)
+                    .padding(10)
+                    .into(),
+            );
+        }
+
+        let diff = self.reload_preview.as_ref()?;
+        let mut lines = column![text("The config file changed:")].spacing(5);
+        for definition in &diff.added {
+            lines = lines.push(text(format!("+ add {}", definition.name)).style(text::success));
+        }
+        for name in &diff.removed {
+            lines = lines.push(text(format!("- remove {} (stops it)", name)).style(text::danger));
+        }
+        for changed in &diff.changed {
+            lines = lines.push(text(format!(
+                "~ change {} ({})",
+                changed.definition.name,
+                changed.fields.join(", ")
+            )));
+        }
+        if diff.unchanged > 0 {
+            lines = lines.push(text(format!("{} unchanged", diff.unchanged)));
+        }
+
+        let apply_button = button("Apply")
+            .style(button::primary)
+            .on_press(Message::ApplyConfigReload);
+        lines = lines.push(row![apply_button, dismiss_button].spacing(10));
+
+        Some(
+            container(lines)
+                .width(Fill)
+                .style(container::rounded_box)
+                .padding(10)
+                .into(),
+        )
+    }
+
+    fn quit_pane(&self) -> Option<Element<'_, Message>> {
+        let running = self
+            .processes()
+            .filter(|(_, process)| {
+                process.is_alive() || matches!(process.status, ProcessStatus::Stopping { .. })
+            })
+            .count();
+        let content = match self.quit.as_ref()? {
+            Quit::Confirming => column![
+                text(format!(
+                    "{} {} still running. Stop {} and quit?",
+                    running,
+                    match running {
+                        1 => "process is",
+                        _ => "processes are",
+                    },
+                    match running {
+                        1 => "it",
+                        _ => "them",
+                    }
+                )),
+                row![
+                    button("Stop and quit")
+                        .style(button::danger)
+                        .on_press(Message::ConfirmQuit),
+                    button("Cancel")
+                        .style(button::secondary)
+                        .on_press(Message::CancelQuit),
+                ]
+                .spacing(10),
+            ],
+            Quit::Stopping => column![
+                text(format!(
+                    "Waiting for {} to exit before quitting...",
+                    running
+                )),
+                button("Kill and quit now")
+                    .style(button::danger)
+                    .on_press(Message::ForceQuit),
+            ],
+        };
+
+        Some(
+            container(content.spacing(5))
+                .width(Fill)
+                .style(container::rounded_box)
+                .padding(10)
+                .into(),
+        )
+    }
+
+    /// Asks whether to start a process's stopped dependencies along with it.
+    fn dependency_pane(&self) -> Option<Element<'_, Message>> {
+        let process_id = self.dependency_prompt?;
+        let process = self.get(process_id)?;
+        let names: Vec<&str> = self
+            .unmet_dependencies(process_id)
+            .into_iter()
+            .filter_map(|dependency| self.get(dependency))
+            .map(|dependency| dependency.name.as_str())
+            .collect();
+
+        let buttons = row![
+            button("Start them first")
+                .style(button::primary)
+                .on_press(Message::StartWithDependencies(process_id)),
+            button(text(format!("Start {} only", process.name)))
+                .style(button::secondary)
+                .on_press(Message::StartWithoutDependencies(process_id)),
+            button("Cancel")
+                .style(button::secondary)
+                .on_press(Message::DismissDependencyPrompt),
+        ]
+        .spacing(10);
+        let content = column![
+            text(format!(
+                "{} depends on {}, which {} not running.",
+                process.name,
+                names.join(", "),
+                match names.len() {
+                    1 => "is",
+                    _ => "are",
+                }
+            )),
+            buttons,
+        ]
+        .spacing(5);
+
+        Some(
+            container(content)
+                .width(Fill)
+                .style(container::rounded_box)
+                .padding(10)
+                .into(),
+        )
+    }
+
+    pub fn focus(&mut self, process_id: ProcessId) -> Task<Message> {
+        self.focused_process = Some(process_id);
+        self.stop_browsing_history();
+        self.log_viewport.scroll_to_bottom()
+    }
+
+    pub fn input_changed(&mut self, input: String) -> Task<Message> {
+        self.input = input;
+        self.stop_browsing_history();
+        Task::none()
+    }
+
+    fn stop_browsing_history(&mut self) {
+        self.history_position = None;
+        self.draft.clear();
+    }
+
+    pub fn submit_input(&mut self) -> Task<Message> {
+        let line = std::mem::take(&mut self.input);
+        self.send_input(Input::Line(line))
+    }
+
+    pub fn send_input(&mut self, input: Input) -> Task<Message> {
+        let Some(process) = self.focused_process.and_then(|id| self.get_mut(id)) else {
+            return Task::none();
+        };
+        process.send_input(input);
+        self.stop_browsing_history();
+        self.log_viewport.scroll_to_bottom()
+    }
+
+    /// The arrow keys reach here wherever the focus is, so they only go
+    /// through the history once the input line is found to have it.
+    pub fn history_key_pressed(&self, step: HistoryStep) -> Task<Message> {
+        widget::operate(focusable::find_focused()).then(move |focused| {
+            match focused == widget::Id::from(input_id()) {
+                true => Task::done(Message::InputHistory(step)),
+                false => Task::none(),
+            }
+        })
+    }
+
+    /// Puts an earlier or later line sent to the focused process in the
+    /// input line. Going past the newest brings back what was typed before.
+    pub fn input_history(&mut self, step: HistoryStep) -> Task<Message> {
+        let Some(process) = self.focused() else {
+            return Task::none();
+        };
+        let history = &process.input_history;
+        let position = match (step, self.history_position) {
+            (_, _) if history.is_empty() => None,
+            (HistoryStep::Older, None) => Some(history.len() - 1),
+            (HistoryStep::Older, Some(position)) => Some(position.saturating_sub(1)),
+            (HistoryStep::Newer, None) => None,
+            (HistoryStep::Newer, Some(position)) => {
+                Some(position + 1).filter(|&position| position < history.len())
+            }
+        };
+        let line = position.map(|position| history[position].clone());
+
+        match (self.history_position, line) {
+            // not browsing, and nothing to browse to
+            (None, None) => return Task::none(),
+            (None, Some(line)) => self.draft = std::mem::replace(&mut self.input, line),
+            (Some(_), Some(line)) => self.input = line,
+            (Some(_), None) => self.input = std::mem::take(&mut self.draft),
+        }
+        self.history_position = position;
+        text_input::move_cursor_to_end(input_id())
+    }
+
+    fn input_line(&self) -> Element<'_, Message> {
+        let accepts_input = self
+            .focused()
+            .is_some_and(|process| process.accepts_input());
+        let input = text_input("Send a line to the process's stdin", &self.input)
+            .id(input_id())
+            .font(iced::Font::MONOSPACE)
+            .on_input_maybe(accepts_input.then_some(Message::InputChanged))
+            .on_submit_maybe(accepts_input.then_some(Message::SubmitInput));
+        let eof_button = button("EOF")
+            .style(button::secondary)
+            .on_press_maybe(accepts_input.then_some(Message::SendInput(Input::Eof)));
+        let interrupt_button = button("Ctrl+C")
+            .style(button::secondary)
+            .on_press_maybe(accepts_input.then_some(Message::SendInput(Input::Interrupt)));
+        row![input, eof_button, interrupt_button]
+            .spacing(5)
+            .padding(5)
+            .into()
+    }
+
+    pub fn show_streams(&mut self, filter: StreamFilter) -> Task<Message> {
+        self.stream_filter = filter;
+        self.log_viewport.scroll_to_bottom()
+    }
+
+    pub fn log_scrolled(&mut self, viewport: scrollable::Viewport) -> Task<Message> {
+        self.log_viewport = LogViewport::scrolled(viewport);
         Task::none()
     }
 
+    pub fn pane_resized(&mut self, size: Size) -> Task<Message> {
+        self.log_viewport.resized(size);
+        let pane_size = log_view::window_size(size);
+        if self.pane_size != Some(pane_size) {
+            self.pane_size = Some(pane_size);
+            for process in self.hosted_processes.iter_mut() {
+                process.pane_resized(pane_size);
+            }
+        }
+        match self.pending_auto_start.take() {
+            Some(sender) => self.auto_start(&sender),
+            None => Task::none(),
+        }
+    }
+
     pub fn toggle_side_bar(&mut self) -> Task<Message> {
         self.show_side_bar = !self.show_side_bar;
         Task::none()
     }
 }
+
+fn input_id() -> text_input::Id {
+    text_input::Id::new("input line")
+}
+
+fn start_process(process: &mut HostedProcess, sender: &Sender<Message>) {
+    if let Err(e) = process.start(sender.clone()) {
+        writeln!(process.output, "error starting process: {}", e).unwrap();
+        process.failed_to_start(e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::scrollback::ScrollbackSettings;
+
+    // db, and web which depends on it
+    fn home() -> HomeScreen {
+        let mut web = ProcessDefinition::named("web");
+        web.depends_on = vec!["db".to_string()];
+        let processes = vec![ProcessDefinition::named("db"), web];
+        HomeScreen::new(Rc::new(processes), ScrollbackSettings::default().total)
+    }
+
+    fn set_status(home: &mut HomeScreen, name: &str, status: ProcessStatus) -> RunId {
+        let process_id = home.id_of(name).unwrap();
+        let process = home.get_mut(process_id).unwrap();
+        process.status = status;
+        process.run_id()
+    }
+
+    fn status<'a>(home: &'a HomeScreen, name: &str) -> &'a ProcessStatus {
+        &home.get(home.id_of(name).unwrap()).unwrap().status
+    }
+
+    #[test]
+    fn losing_a_dependency_fails_what_waits_for_it() {
+        let mut home = home();
+        let since = Instant::now();
+        let db = set_status(&mut home, "db", ProcessStatus::Starting { pid: 1, since });
+        set_status(&mut home, "web", ProcessStatus::Waiting { since });
+
+        let _ = home.process_lost(db, "no child processes".to_string(), Instant::now());
+        assert!(matches!(
+            status(&home, "db"),
+            ProcessStatus::Crashed { code: -1, .. }
+        ));
+        assert!(matches!(
+            status(&home, "web"),
+            ProcessStatus::FailedToStart { .. }
+        ));
+    }
+
+    #[test]
+    fn losing_a_dependent_goes_on_stopping_everything() {
+        let mut home = home();
+        let since = Instant::now();
+        set_status(&mut home, "db", ProcessStatus::Running { pid: 1, since });
+        let web = set_status(&mut home, "web", ProcessStatus::Running { pid: 2, since });
+
+        // db waits for web to exit first
+        let _ = home.stop_all();
+        assert!(matches!(
+            status(&home, "web"),
+            ProcessStatus::Stopping { .. }
+        ));
+        assert!(matches!(status(&home, "db"), ProcessStatus::Running { .. }));
+
+        let _ = home.process_lost(web, "no child processes".to_string(), Instant::now());
+        assert!(matches!(
+            status(&home, "db"),
+            ProcessStatus::Stopping { .. }
+        ));
+    }
+
+    #[test]
+    fn stepping_through_the_history_and_back_to_the_draft() {
+        let mut home = home();
+        home.hosted_processes[0].input_history = vec![
+            "first".to_string(),
+            "second".to_string(),
+            "third".to_string(),
+        ];
+        let _ = home.input_changed("draft".to_string());
+
+        let mut step = |step| {
+            let _ = home.input_history(step);
+            home.input.clone()
+        };
+        assert_eq!(step(HistoryStep::Older), "third");
+        assert_eq!(step(HistoryStep::Older), "second");
+        assert_eq!(step(HistoryStep::Older), "first");
+        // the oldest line stays put
+        assert_eq!(step(HistoryStep::Older), "first");
+        assert_eq!(step(HistoryStep::Newer), "second");
+        assert_eq!(step(HistoryStep::Newer), "third");
+        assert_eq!(step(HistoryStep::Newer), "draft");
+        assert_eq!(step(HistoryStep::Newer), "draft");
+    }
+
+    #[test]
+    fn editing_a_history_line_makes_it_the_draft() {
+        let mut home = home();
+        home.hosted_processes[0].input_history = vec!["first".to_string(), "second".to_string()];
+
+        let _ = home.input_history(HistoryStep::Older);
+        let _ = home.input_changed("second, edited".to_string());
+        let _ = home.input_history(HistoryStep::Older);
+        assert_eq!(home.input, "second");
+        let _ = home.input_history(HistoryStep::Newer);
+        assert_eq!(home.input, "second, edited");
+    }
+
+    #[test]
+    fn no_history_leaves_the_input_alone() {
+        let mut home = home();
+        let _ = home.input_changed("typed".to_string());
+        let _ = home.input_history(HistoryStep::Older);
+        assert_eq!(home.input, "typed");
+        let _ = home.input_history(HistoryStep::Newer);
+        assert_eq!(home.input, "typed");
+    }
+}
@@ -0,0 +1,242 @@
+//! Shows a process's scrollback by laying out only the lines around the part
+//! in view, so a log of any length costs the same to draw. Every line is one
+//! row of a fixed height, so long lines scroll sideways instead of wrapping.
+//! Lines that set colours with escape sequences are drawn as rich text.
+
+use iced::Length::Fill;
+use iced::widget::scrollable::{self, AbsoluteOffset, Direction, Scrollbar, Viewport};
+use iced::widget::text::{LineHeight, Span, Wrapping};
+use iced::widget::{Column, Space, container, rich_text, text};
+use iced::{Color, Element, Font, Size, Task, font};
+
+use crate::Message;
+use crate::ansi::{self, Style};
+use crate::config::tty::WindowSize;
+use crate::scrollback::{Line, Scrollback, Stream, StreamFilter};
+
+const LINE_HEIGHT: f32 = 20.0;
+const TEXT_SIZE: f32 = 14.0;
+// near enough for the monospace fonts iced picks
+const CHAR_WIDTH: f32 = TEXT_SIZE * 0.6;
+const PADDING: f32 = 10.0;
+
+/// Where the log is scrolled to. The view is anchored to the bottom, so it
+/// follows new output for as long as `from_bottom` is zero.
+#[derive(Debug, Clone, Copy)]
+pub struct LogViewport {
+    from_bottom: f32,
+    height: f32,
+}
+
+impl Default for LogViewport {
+    fn default() -> Self {
+        Self {
+            from_bottom: 0.0,
+            // generous, until the pane is first measured
+            height: 2000.0,
+        }
+    }
+}
+
+impl LogViewport {
+    pub fn scrolled(viewport: Viewport) -> Self {
+        Self {
+            from_bottom: viewport.absolute_offset().y,
+            height: viewport.bounds().height,
+        }
+    }
+
+    pub fn resized(&mut self, size: Size) {
+        self.height = size.height;
+    }
+
+    /// Goes back to following new output.
+    pub fn scroll_to_bottom(&mut self) -> Task<Message> {
+        self.from_bottom = 0.0;
+        scrollable::scroll_to(id(), AbsoluteOffset { x: 0.0, y: 0.0 })
+    }
+}
+
+/// How many characters fit in a log of `size`, for a terminal the same size.
+pub fn window_size(size: Size) -> WindowSize {
+    // leaving room for the padding and a scrollbar
+    let columns = (size.width - 3.0 * PADDING) / CHAR_WIDTH;
+    let rows = size.height / LINE_HEIGHT;
+    WindowSize {
+        columns: columns.max(1.0) as u16,
+        rows: rows.max(1.0) as u16,
+    }
+}
+
+fn id() -> scrollable::Id {
+    scrollable::Id::new("log view")
+}
+
+/// Shows the lines `filter` picks out, with stderr in the danger colour and
+/// multi-host's own notes dimmed. Colours the process set are left out if
+/// `strip_color` is set.
+pub fn view(
+    scrollback: &Scrollback,
+    filter: StreamFilter,
+    strip_color: bool,
+    viewport: LogViewport,
+) -> Element<'_, Message> {
+    // the dropped lines marker takes the first row
+    let marker = usize::from(scrollback.dropped() > 0);
+    let rows = scrollback.len_shown(filter) + marker;
+
+    // A screenful either side covers the window changing size, until the new
+    // size is reported.
+    let content_height = rows as f32 * LINE_HEIGHT;
+    let top = content_height - viewport.height - viewport.from_bottom;
+    let last = ((top + 2.0 * viewport.height) / LINE_HEIGHT)
+        .ceil()
+        .max(0.0) as usize;
+    let last = last.min(rows);
+    let first = ((top - viewport.height) / LINE_HEIGHT).floor().max(0.0) as usize;
+    let first = first.min(last);
+
+    let mut lines = Column::new().push(Space::with_height(first as f32 * LINE_HEIGHT));
+    if marker == 1 && first == 0 {
+        let dropped = format!("[{} earlier lines dropped]", scrollback.dropped());
+        lines = lines.push(row(dropped, text::secondary));
+    }
+    let end = last.saturating_sub(marker);
+    let start = first.saturating_sub(marker).min(end);
+    for line in scrollback.lines(filter, start..end) {
+        let style = match line.stream {
+            Stream::Stdout => text::base,
+            Stream::Stderr => text::danger,
+            Stream::Note => text::secondary,
+        };
+        lines = match strip_color || line.styles.is_empty() {
+            true => lines.push(row(line.text.as_str(), style)),
+            false => lines.push(styled_row(line, style)),
+        };
+    }
+    lines = lines.push(Space::with_height((rows - last) as f32 * LINE_HEIGHT));
+
+    iced::widget::scrollable(container(lines).padding([0.0, PADDING]))
+        .id(id())
+        .direction(Direction::Both {
+            vertical: Scrollbar::default(),
+            horizontal: Scrollbar::default(),
+        })
+        .anchor_bottom()
+        .on_scroll(Message::LogScrolled)
+        .width(Fill)
+        .height(Fill)
+        .into()
+}
+
+fn row<'a>(
+    line: impl text::IntoFragment<'a>,
+    style: fn(&iced::Theme) -> text::Style,
+) -> Element<'a, Message> {
+    container(
+        text(line)
+            .font(Font::MONOSPACE)
+            .size(TEXT_SIZE)
+            .line_height(LineHeight::Absolute(LINE_HEIGHT.into()))
+            .wrapping(Wrapping::None)
+            .style(style),
+    )
+    .height(LINE_HEIGHT)
+    .into()
+}
+
+fn styled_row(line: &Line, style: fn(&iced::Theme) -> text::Style) -> Element<'_, Message> {
+    let spans: Vec<Span<Message>> = line
+        .spans()
+        .map(|(text, style)| span(text, style))
+        .collect();
+    container(
+        rich_text(spans)
+            .font(Font::MONOSPACE)
+            .size(TEXT_SIZE)
+            .line_height(LineHeight::Absolute(LINE_HEIGHT.into()))
+            .wrapping(Wrapping::None)
+            .style(style),
+    )
+    .height(LINE_HEIGHT)
+    .into()
+}
+
+fn span(text: &str, style: Style) -> Span<'_, Message> {
+    let (mut foreground, mut background) = (style.foreground, style.background);
+    if style.inverse {
+        // with nothing to swap, the default colours are close enough to these
+        foreground = Some(background.unwrap_or(ansi::Color::Indexed(0)));
+        background = Some(style.foreground.unwrap_or(ansi::Color::Indexed(7)));
+    }
+    let mut foreground = foreground.map(color);
+    if style.dim {
+        foreground = Some(Color {
+            a: 0.6,
+            ..foreground.unwrap_or(color(ansi::Color::Indexed(8)))
+        });
+    }
+    let font = Font {
+        weight: match style.bold {
+            true => font::Weight::Bold,
+            false => font::Weight::Normal,
+        },
+        style: match style.italic {
+            true => font::Style::Italic,
+            false => font::Style::Normal,
+        },
+        ..Font::MONOSPACE
+    };
+
+    let span = Span::new(text)
+        .font(font)
+        .color_maybe(foreground)
+        .underline(style.underline)
+        .strikethrough(style.strikethrough);
+    match background {
+        Some(background) => span.background(color(background)),
+        None => span,
+    }
+}
+
+/// The 16 named colours, as xterm draws them.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn color(color: ansi::Color) -> Color {
+    let (r, g, b) = match color {
+        ansi::Color::Rgb(r, g, b) => (r, g, b),
+        ansi::Color::Indexed(index @ 0..16) => PALETTE[index as usize],
+        // a 6x6x6 cube
+        ansi::Color::Indexed(index @ 16..232) => {
+            let level = |n: u8| match n {
+                0 => 0,
+                n => 55 + n * 40,
+            };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        // then a ramp of greys
+        ansi::Color::Indexed(index) => {
+            let grey = 8 + (index - 232) * 10;
+            (grey, grey, grey)
+        }
+    };
+    Color::from_rgb8(r, g, b)
+}
@@ -1,2 +1,4 @@
 pub mod home;
+pub mod log_view;
+pub mod measured;
 pub mod settings;
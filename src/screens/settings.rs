@@ -35,7 +35,7 @@ impl SettingsScreen {
         }
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let back_button = button("Back")
             .style(button::secondary)
             .on_press(Message::ChangeScreen(Screen::Home));
@@ -0,0 +1,417 @@
+//! The output kept for each hosted process. It holds whole lines and drops
+//! the oldest once it goes over its limits, so a chatty process can run all
+//! day without eating up memory.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+
+use crate::ansi::{Style, StyleChange};
+use crate::config::scrollback::ScrollbackLimits;
+
+/// Where a line of output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    /// Written by multi-host itself, e.g. that the process exited.
+    Note,
+}
+
+/// A line of output, with its escape sequences taken out of `text` and kept
+/// as `styles` instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub stream: Stream,
+    pub text: String,
+    pub styles: Vec<StyleChange>,
+    /// The process hasn't finished the line yet, so the next line from its
+    /// stream takes its place.
+    pub partial: bool,
+}
+
+impl Line {
+    pub fn new(stream: Stream, text: String) -> Self {
+        Self::styled(stream, text, vec![])
+    }
+
+    pub fn styled(stream: Stream, text: String, styles: Vec<StyleChange>) -> Self {
+        Self {
+            stream,
+            text,
+            styles,
+            partial: false,
+        }
+    }
+
+    /// Puts `prefix`, unstyled, in front of the line.
+    pub fn prefixed(mut self, prefix: &str) -> Self {
+        self.text.insert_str(0, prefix);
+        for change in &mut self.styles {
+            change.at += prefix.len();
+        }
+        self
+    }
+
+    /// The pieces of the line that share a style.
+    pub fn spans(&self) -> impl Iterator<Item = (&str, Style)> {
+        let starts = std::iter::once((0, Style::default()))
+            .chain(self.styles.iter().map(|change| (change.at, change.style)));
+        let ends = self
+            .styles
+            .iter()
+            .map(|change| change.at)
+            .chain(std::iter::once(self.text.len()));
+        starts
+            .zip(ends)
+            .filter(|((start, _), end)| start < end)
+            .map(|((start, style), end)| (&self.text[start..end], style))
+    }
+}
+
+/// Which lines to show. Notes are shown whichever stream is picked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamFilter {
+    #[default]
+    Both,
+    Stdout,
+    Stderr,
+}
+
+impl StreamFilter {
+    pub const ALL: [StreamFilter; 3] = [
+        StreamFilter::Both,
+        StreamFilter::Stdout,
+        StreamFilter::Stderr,
+    ];
+
+    fn shows(self, stream: Stream) -> bool {
+        !matches!(
+            (self, stream),
+            (StreamFilter::Stdout, Stream::Stderr) | (StreamFilter::Stderr, Stream::Stdout)
+        )
+    }
+}
+
+impl fmt::Display for StreamFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamFilter::Both => write!(f, "both"),
+            StreamFilter::Stdout => write!(f, "stdout"),
+            StreamFilter::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<Line>,
+    // the number of lines ever pushed before the first one in `lines`
+    first: u64,
+    // lines dropped before they were ever pushed, when the UI fell behind
+    dropped_unseen: u64,
+    // the numbers of the lines `StreamFilter::Stdout` and `Stderr` show, so
+    // a filtered view can be sliced as cheaply as the whole one
+    stdout_lines: VecDeque<u64>,
+    stderr_lines: VecDeque<u64>,
+    // the numbers of the partial stdout and stderr lines, if they are kept
+    unfinished_stdout: Option<u64>,
+    unfinished_stderr: Option<u64>,
+    // the length of everything in `lines`
+    bytes: usize,
+    // a line being written a piece at a time through `fmt::Write`
+    partial: String,
+    limits: ScrollbackLimits,
+}
+
+impl Scrollback {
+    pub fn new(limits: ScrollbackLimits) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            first: 0,
+            dropped_unseen: 0,
+            stdout_lines: VecDeque::new(),
+            stderr_lines: VecDeque::new(),
+            unfinished_stdout: None,
+            unfinished_stderr: None,
+            bytes: 0,
+            partial: String::new(),
+            limits,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: ScrollbackLimits) {
+        self.limits = limits;
+        self.trim();
+    }
+
+    pub fn push(&mut self, line: Line) {
+        let partial = line.partial;
+        let unfinished = match line.stream {
+            Stream::Stdout => &mut self.unfinished_stdout,
+            Stream::Stderr => &mut self.unfinished_stderr,
+            Stream::Note => return self.append(line),
+        };
+        let previous = match partial {
+            true => unfinished.replace(self.first + self.lines.len() as u64),
+            false => unfinished.take(),
+        };
+
+        match previous {
+            Some(number) if number >= self.first => {
+                // it keeps its place, and the number it was given
+                *unfinished = partial.then_some(number);
+                let index = (number - self.first) as usize;
+                self.bytes -= self.lines[index].text.len();
+                self.bytes += line.text.len();
+                self.lines[index] = line;
+                self.trim();
+            }
+            _ => self.append(line),
+        }
+    }
+
+    fn append(&mut self, line: Line) {
+        let number = self.first + self.lines.len() as u64;
+        if StreamFilter::Stdout.shows(line.stream) {
+            self.stdout_lines.push_back(number);
+        }
+        if StreamFilter::Stderr.shows(line.stream) {
+            self.stderr_lines.push_back(number);
+        }
+        self.bytes += line.text.len();
+        self.lines.push_back(line);
+        self.trim();
+    }
+
+    /// Drops the oldest line, returning its length.
+    pub fn drop_oldest(&mut self) -> Option<usize> {
+        let line = self.lines.pop_front()?;
+        for numbers in [&mut self.stdout_lines, &mut self.stderr_lines] {
+            if numbers.front() == Some(&self.first) {
+                numbers.pop_front();
+            }
+        }
+        self.first += 1;
+        self.bytes -= line.text.len();
+        Some(line.text.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// How many lines have been dropped, to stay within the limits or
+    /// because they came faster than they could be shown.
+    pub fn dropped(&self) -> u64 {
+        self.first + self.dropped_unseen
+    }
+
+    /// Counts lines that were dropped on their way here.
+    pub fn count_dropped(&mut self, lines: u64) {
+        self.dropped_unseen += lines;
+    }
+
+    /// How many of the lines kept `filter` shows.
+    pub fn len_shown(&self, filter: StreamFilter) -> usize {
+        match filter {
+            StreamFilter::Both => self.lines.len(),
+            StreamFilter::Stdout => self.stdout_lines.len(),
+            StreamFilter::Stderr => self.stderr_lines.len(),
+        }
+    }
+
+    /// The lines in `range` of those `filter` shows, counting from the oldest
+    /// one kept.
+    pub fn lines(
+        &self,
+        filter: StreamFilter,
+        range: Range<usize>,
+    ) -> Box<dyn Iterator<Item = &Line> + '_> {
+        let numbers = match filter {
+            StreamFilter::Both => return Box::new(self.lines.range(range)),
+            StreamFilter::Stdout => &self.stdout_lines,
+            StreamFilter::Stderr => &self.stderr_lines,
+        };
+        Box::new(
+            numbers
+                .range(range)
+                .map(|number| &self.lines[(number - self.first) as usize]),
+        )
+    }
+
+    // A single line bigger than the byte limit is still kept, since there
+    // would be nothing to show otherwise.
+    fn trim(&mut self) {
+        while self.lines.len() > self.limits.lines
+            || (self.bytes > self.limits.bytes && self.lines.len() > 1)
+        {
+            self.drop_oldest();
+        }
+    }
+}
+
+/// What multi-host writes about a process goes in as notes.
+impl fmt::Write for Scrollback {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let mut pieces = text.split('\n');
+        if let Some(first) = pieces.next() {
+            self.partial.push_str(first);
+        }
+        for piece in pieces {
+            let line = std::mem::replace(&mut self.partial, piece.to_string());
+            self.push(Line::new(Stream::Note, line));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    fn scrollback(lines: usize, bytes: usize) -> Scrollback {
+        Scrollback::new(ScrollbackLimits { lines, bytes })
+    }
+
+    fn line(text: &str) -> Line {
+        Line::new(Stream::Stdout, text.to_string())
+    }
+
+    fn shown(scrollback: &Scrollback, filter: StreamFilter) -> Vec<&str> {
+        let len = scrollback.len_shown(filter);
+        scrollback
+            .lines(filter, 0..len)
+            .map(|line| line.text.as_str())
+            .collect()
+    }
+
+    fn kept(scrollback: &Scrollback) -> Vec<&str> {
+        shown(scrollback, StreamFilter::Both)
+    }
+
+    #[test]
+    fn oldest_lines_are_dropped_over_the_line_limit() {
+        let mut scrollback = scrollback(2, usize::MAX);
+        for text in ["one", "two", "three"] {
+            scrollback.push(line(text));
+        }
+        assert_eq!(kept(&scrollback), ["two", "three"]);
+        assert_eq!(scrollback.bytes(), "twothree".len());
+        assert_eq!(scrollback.dropped(), 1);
+    }
+
+    #[test]
+    fn byte_limit_keeps_at_least_one_line() {
+        let mut scrollback = scrollback(10, 8);
+        scrollback.push(line("abcd"));
+        scrollback.push(line("efgh"));
+        assert_eq!(kept(&scrollback), ["abcd", "efgh"]);
+
+        scrollback.push(line("a line longer than the limit"));
+        assert_eq!(kept(&scrollback), ["a line longer than the limit"]);
+        assert_eq!(scrollback.dropped(), 2);
+    }
+
+    #[test]
+    fn tighter_limits_trim_straight_away() {
+        let mut scrollback = scrollback(10, usize::MAX);
+        for text in ["one", "two", "three"] {
+            scrollback.push(line(text));
+        }
+        scrollback.set_limits(ScrollbackLimits {
+            lines: 1,
+            bytes: usize::MAX,
+        });
+        assert_eq!(kept(&scrollback), ["three"]);
+    }
+
+    #[test]
+    fn writes_are_kept_once_their_line_ends() {
+        let mut scrollback = scrollback(10, usize::MAX);
+        write!(scrollback, "process ").unwrap();
+        writeln!(scrollback, "exited").unwrap();
+        write!(scrollback, "first\nsecond\nunfinished").unwrap();
+        assert_eq!(kept(&scrollback), ["process exited", "first", "second"]);
+    }
+
+    #[test]
+    fn trimming_keeps_the_stream_indices_in_step() {
+        let mut scrollback = scrollback(3, usize::MAX);
+        for (stream, text) in [
+            (Stream::Stdout, "out 1"),
+            (Stream::Stderr, "err 1"),
+            (Stream::Stdout, "out 2"),
+            (Stream::Note, "note"),
+            (Stream::Stderr, "err 2"),
+        ] {
+            scrollback.push(Line::new(stream, text.to_string()));
+        }
+
+        assert_eq!(scrollback.dropped(), 2);
+        assert_eq!(
+            shown(&scrollback, StreamFilter::Both),
+            ["out 2", "note", "err 2"]
+        );
+        // notes show whichever stream is picked
+        assert_eq!(shown(&scrollback, StreamFilter::Stdout), ["out 2", "note"]);
+        assert_eq!(shown(&scrollback, StreamFilter::Stderr), ["note", "err 2"]);
+    }
+
+    fn partial(stream: Stream, text: &str) -> Line {
+        Line {
+            partial: true,
+            ..Line::new(stream, text.to_string())
+        }
+    }
+
+    #[test]
+    fn partial_line_is_replaced_in_place() {
+        let mut scrollback = scrollback(10, usize::MAX);
+        scrollback.push(partial(Stream::Stdout, "Password"));
+        scrollback.push(Line::new(Stream::Stderr, "warning".to_string()));
+        scrollback.push(partial(Stream::Stdout, "Password:"));
+        scrollback.push(Line::new(Stream::Stdout, "Password: ok".to_string()));
+        scrollback.push(Line::new(Stream::Stdout, "next".to_string()));
+
+        assert_eq!(kept(&scrollback), ["Password: ok", "warning", "next"]);
+        assert_eq!(scrollback.bytes(), "Password: okwarningnext".len());
+    }
+
+    #[test]
+    fn partial_lines_of_different_streams_are_kept_apart() {
+        let mut scrollback = scrollback(10, usize::MAX);
+        scrollback.push(partial(Stream::Stdout, "out"));
+        scrollback.push(partial(Stream::Stderr, "err"));
+        scrollback.push(Line::new(Stream::Stderr, "err done".to_string()));
+        scrollback.push(Line::new(Stream::Stdout, "out done".to_string()));
+
+        assert_eq!(kept(&scrollback), ["out done", "err done"]);
+    }
+
+    #[test]
+    fn trimmed_partial_line_is_not_replaced() {
+        let mut scrollback = scrollback(2, usize::MAX);
+        scrollback.push(partial(Stream::Stdout, "loading"));
+        scrollback.push(Line::new(Stream::Stderr, "err 1".to_string()));
+        scrollback.push(Line::new(Stream::Stderr, "err 2".to_string()));
+        scrollback.push(Line::new(Stream::Stdout, "loaded".to_string()));
+
+        assert_eq!(kept(&scrollback), ["err 2", "loaded"]);
+        assert_eq!(shown(&scrollback, StreamFilter::Stdout), ["loaded"]);
+    }
+
+    #[test]
+    fn lines_dropped_on_the_way_count_as_dropped() {
+        let mut scrollback = scrollback(1, usize::MAX);
+        scrollback.push(line("1"));
+        scrollback.push(line("2"));
+        scrollback.count_dropped(5);
+        assert_eq!(scrollback.dropped(), 6);
+    }
+}
@@ -0,0 +1,50 @@
+//! Turns SIGINT and SIGTERM into `Message::ShutdownSignal`, so that Ctrl+C in
+//! the terminal stops the hosted processes instead of orphaning them.
+
+use async_std::task;
+use iced::futures::{SinkExt, Stream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::Message;
+
+/// Signals received so far. The handler can only safely touch an atomic, so
+/// the subscription polls this.
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+pub fn install_handlers() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        SIGNALS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic, which is signal safe
+        unsafe {
+            libc::signal(
+                signal,
+                handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handlers() {}
+
+/// Emits one `Message::ShutdownSignal` per signal received.
+pub fn signals() -> impl Stream<Item = Message> {
+    iced::stream::channel(1, |mut output| async move {
+        // starting from zero picks up signals that arrived before the UI did
+        let mut seen = 0;
+        loop {
+            task::sleep(Duration::from_millis(100)).await;
+
+            let received = SIGNALS.load(Ordering::SeqCst);
+            for _ in seen..received {
+                let _ = output.send(Message::ShutdownSignal).await;
+            }
+            seen = received;
+        }
+    })
+}